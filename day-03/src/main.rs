@@ -1,93 +1,381 @@
+use std::env;
+
 use util::*;
 
+// Bits are packed most significant first so that the derived `Ord` sorts
+// equal width reports the same as their binary strings
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Bits {
+    len: usize,
+    words: Vec<u64>,
+}
+
+impl Bits {
+    fn zero(len: usize) -> Self {
+        Self {
+            len,
+            words: vec![0; len.div_ceil(64)],
+        }
+    }
+
+    // Bit `0` is the most significant (left-most) bit of the report line
+    fn get(&self, i: usize) -> bool {
+        self.words[i / 64] & (1 << (63 - i % 64)) != 0
+    }
+
+    fn set(&mut self, i: usize) {
+        self.words[i / 64] |= 1 << (63 - i % 64);
+    }
+
+    fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(w, word)| {
+            let mut word = *word;
+
+            iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+
+                let i = word.leading_zeros() as usize;
+
+                word &= !(1 << (63 - i));
+
+                Some(w * 64 + i)
+            })
+        })
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        if self.len > 64 {
+            return None;
+        }
+
+        Some((0..self.len).fold(0, |n, i| (n << 1) | u64::from(self.get(i))))
+    }
+}
+
+impl FromStr for Bits {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bits = Self::zero(s.len());
+
+        for (i, c) in s.chars().enumerate() {
+            match c {
+                '0' => {}
+                '1' => bits.set(i),
+                _ => bail!("Invalid bit: {}", c),
+            }
+        }
+
+        Ok(bits)
+    }
+}
+
+impl fmt::Display for Bits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for i in 0..self.len {
+            write!(f, "{}", if self.get(i) { '1' } else { '0' })?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 struct Puzzle {
     len: usize,
-    nums: Vec<u64>,
+    // Sorted, so that numbers sharing a prefix are contiguous
+    nums: Vec<Bits>,
 }
 
 impl Puzzle {
     fn parse(input: &str) -> Result<Self, Error> {
-        let len = input
+        let mut nums = input
             .trim()
             .lines()
-            .map(|line| line.trim().len())
-            .max()
-            .context("Expected puzzle input")?;
-
-        let nums = input
-            .trim()
-            .lines()
-            .map(|line| u64::from_str_radix(line.trim(), 2))
+            .map(|line| Bits::from_str(line.trim()))
             .collect::<Result<Vec<_>, _>>()?;
 
+        let len = nums.first().context("Expected puzzle input")?.len;
+
+        if let Some((i, n)) = nums.iter().enumerate().find(|(_, n)| n.len != len) {
+            bail!("Line {} has width {}, expected {}", i + 1, n.len, len);
+        }
+
+        nums.sort_unstable();
+
         Ok(Self { nums, len })
     }
 
-    fn gamma_epsilon(&self) -> (u64, u64) {
-        let mut gamma = 0;
-        let mut epsilon = 0;
-        let mut mask = 1;
+    // Number of ones in each bit position, counted in a single pass
+    fn column_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.len];
+
+        for i in self.nums.iter().flat_map(Bits::ones) {
+            counts[i] += 1;
+        }
+
+        counts
+    }
 
-        for _ in 0..self.len {
-            let count = self.nums.iter().filter(|n| *n & mask != 0).count();
+    fn gamma_epsilon(&self) -> (Bits, Bits) {
+        let mut gamma = Bits::zero(self.len);
+        let mut epsilon = Bits::zero(self.len);
 
+        for (i, count) in self.column_counts().into_iter().enumerate() {
             if count * 2 >= self.nums.len() {
-                gamma |= mask;
+                gamma.set(i);
             } else {
-                epsilon |= mask;
+                epsilon.set(i);
             }
-
-            mask <<= 1;
         }
 
         (gamma, epsilon)
     }
 
-    fn rating(&self, use_gamma: bool) -> u64 {
-        let mut puzzle = self.clone();
-        let mut mask = 1 << (puzzle.len - 1);
+    fn columns(&self) -> Vec<Column> {
+        self.column_counts()
+            .into_iter()
+            .map(|ones| Column {
+                ones,
+                zeros: self.nums.len() - ones,
+            })
+            .collect()
+    }
 
-        while puzzle.nums.len() > 1 {
-            let (gamma, epsilon) = puzzle.gamma_epsilon();
-            let rating = if use_gamma { gamma } else { epsilon };
+    // Since `nums` is sorted, the candidates sharing a prefix are a contiguous
+    // range and each bit splits it into zeros followed by ones
+    fn rounds(&self, use_gamma: bool) -> Vec<Round<'_>> {
+        let mut nums = &self.nums[..];
+        let mut rounds = Vec::new();
+
+        for bit in 0..self.len {
+            if nums.len() <= 1 {
+                break;
+            }
 
-            puzzle.nums = puzzle
-                .nums
-                .into_iter()
-                .filter(|n| rating & mask == n & mask)
-                .collect();
+            let split = nums.partition_point(|n| !n.get(bit));
+            let (zeros, ones) = nums.split_at(split);
+            let column = Column {
+                ones: ones.len(),
+                zeros: zeros.len(),
+            };
 
-            mask >>= 1;
+            let keep = column.most_common() == use_gamma;
+            let next = if keep { ones } else { zeros };
+            let decision = if next.is_empty() {
+                // Every candidate shares this bit
+                Decision::Shared
+            } else {
+                nums = next;
+
+                if column.is_tie() {
+                    Decision::Tie
+                } else {
+                    Decision::Count
+                }
+            };
+
+            rounds.push(Round {
+                bit,
+                column,
+                keep,
+                decision,
+                remaining: nums,
+            });
         }
 
-        puzzle.nums[0]
+        rounds
     }
 
-    fn oxygen(&self) -> u64 {
+    fn rating(&self, use_gamma: bool) -> &Bits {
+        self.rounds(use_gamma)
+            .last()
+            .map(|round| &round.remaining[0])
+            .unwrap_or(&self.nums[0])
+    }
+
+    fn oxygen(&self) -> &Bits {
         self.rating(true)
     }
 
-    fn c02(&self) -> u64 {
+    fn c02(&self) -> &Bits {
         self.rating(false)
     }
 
-    fn part_1(&self) -> u64 {
+    fn product(a: &Bits, b: &Bits) -> Result<u64, Error> {
+        let a = a.to_u64().context("Report is too wide for a u64 product")?;
+        let b = b.to_u64().context("Report is too wide for a u64 product")?;
+
+        a.checked_mul(b).context("Product overflows a u64")
+    }
+
+    fn report(&self) -> Report<'_> {
         let (gamma, epsilon) = self.gamma_epsilon();
 
-        gamma * epsilon
+        Report {
+            gamma,
+            epsilon,
+            columns: self.columns(),
+            oxygen: self.rounds(true),
+            c02: self.rounds(false),
+        }
+    }
+
+    fn part_1(&self) -> Result<u64, Error> {
+        let (gamma, epsilon) = self.gamma_epsilon();
+
+        Self::product(&gamma, &epsilon)
+    }
+
+    fn part_2(&self) -> Result<u64, Error> {
+        Self::product(self.oxygen(), self.c02())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Column {
+    ones: usize,
+    zeros: usize,
+}
+
+impl Column {
+    // Ties are broken towards `1`, matching `count * 2 >= len`
+    fn most_common(&self) -> bool {
+        self.ones >= self.zeros
     }
 
-    fn part_2(&self) -> u64 {
-        self.oxygen() * self.c02()
+    fn is_tie(&self) -> bool {
+        self.ones == self.zeros
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Decision {
+    Count,
+    Tie,
+    Shared,
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Decision::Count => "count",
+            Decision::Tie => "tie",
+            Decision::Shared => "shared",
+        };
+
+        f.pad(s)
+    }
+}
+
+#[derive(Debug)]
+struct Round<'a> {
+    bit: usize,
+    column: Column,
+    // Bit value candidates were filtered on
+    keep: bool,
+    decision: Decision,
+    remaining: &'a [Bits],
+}
+
+#[derive(Debug)]
+struct Report<'a> {
+    gamma: Bits,
+    epsilon: Bits,
+    columns: Vec<Column>,
+    oxygen: Vec<Round<'a>>,
+    c02: Vec<Round<'a>>,
+}
+
+impl Report<'_> {
+    // Candidates are only listed when there are few enough to read
+    const MAX_LISTED: usize = 4;
+
+    fn fmt_rounds(f: &mut fmt::Formatter<'_>, name: &str, rounds: &[Round]) -> fmt::Result {
+        writeln!(f, "{}", name)?;
+        writeln!(f, "  bit   ones  zeros  keep  decision  remaining")?;
+
+        for round in rounds {
+            write!(
+                f,
+                "  {:>3}  {:>5}  {:>5}  {:>4}  {:<8}  {:>9}",
+                round.bit,
+                round.column.ones,
+                round.column.zeros,
+                u8::from(round.keep),
+                round.decision,
+                round.remaining.len(),
+            )?;
+
+            if round.remaining.len() <= Self::MAX_LISTED {
+                write!(f, "  {}", round.remaining.iter().join(" "))?;
+            }
+
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Gamma:   {}", self.gamma)?;
+        writeln!(f, "Epsilon: {}", self.epsilon)?;
+        writeln!(f)?;
+        writeln!(f, "  bit   ones  zeros  gamma  epsilon  tie")?;
+
+        for (bit, column) in self.columns.iter().enumerate() {
+            let gamma = column.most_common();
+
+            write!(
+                f,
+                "  {:>3}  {:>5}  {:>5}  {:>5}  {:>7}",
+                bit,
+                column.ones,
+                column.zeros,
+                u8::from(gamma),
+                u8::from(!gamma),
+            )?;
+
+            if column.is_tie() {
+                write!(f, "  yes")?;
+            }
+
+            writeln!(f)?;
+        }
+
+        writeln!(f)?;
+        Self::fmt_rounds(f, "Oxygen", &self.oxygen)?;
+        writeln!(f)?;
+        Self::fmt_rounds(f, "CO2", &self.c02)
     }
 }
 
 fn main() -> Result<(), Error> {
     let puzzle = Puzzle::parse(&read_stdin()?)?;
 
-    println!("Part 1: {}", puzzle.part_1());
-    println!("Part 2: {}", puzzle.part_2());
+    if env::args().nth(1).as_deref() == Some("report") {
+        print!("{}", puzzle.report());
+
+        return Ok(());
+    }
+
+    match puzzle.part_1() {
+        Ok(n) => println!("Part 1: {}", n),
+        Err(_) => {
+            let (gamma, epsilon) = puzzle.gamma_epsilon();
+
+            println!("Part 1: {} * {}", gamma, epsilon);
+        }
+    }
+
+    match puzzle.part_2() {
+        Ok(n) => println!("Part 2: {}", n),
+        Err(_) => println!("Part 2: {} * {}", puzzle.oxygen(), puzzle.c02()),
+    }
 
     Ok(())
 }
@@ -113,25 +401,104 @@ mod test {
 
     #[test]
     fn part_1() -> Result<(), Error> {
-        assert_eq!(Puzzle::parse(INPUT)?.part_1(), 198);
+        assert_eq!(Puzzle::parse(INPUT)?.part_1()?, 198);
         Ok(())
     }
 
     #[test]
     fn part_2() -> Result<(), Error> {
-        assert_eq!(Puzzle::parse(INPUT)?.part_2(), 230);
+        assert_eq!(Puzzle::parse(INPUT)?.part_2()?, 230);
         Ok(())
     }
 
     #[test]
     fn oxygen() -> Result<(), Error> {
-        assert_eq!(Puzzle::parse(INPUT)?.oxygen(), 23);
+        assert_eq!(Puzzle::parse(INPUT)?.oxygen().to_u64(), Some(23));
         Ok(())
     }
 
     #[test]
     fn c02() -> Result<(), Error> {
-        assert_eq!(Puzzle::parse(INPUT)?.c02(), 10);
+        assert_eq!(Puzzle::parse(INPUT)?.c02().to_u64(), Some(10));
+        Ok(())
+    }
+
+    #[test]
+    fn wide() -> Result<(), Error> {
+        // Repeat every line to make a 105-bit wide report
+        let input = INPUT
+            .trim()
+            .lines()
+            .map(|line| line.trim().repeat(21))
+            .join("\n");
+
+        let puzzle = Puzzle::parse(&input)?;
+        let (gamma, epsilon) = puzzle.gamma_epsilon();
+
+        assert_eq!(puzzle.len, 105);
+        assert_eq!(gamma.to_string(), "10110".repeat(21));
+        assert_eq!(epsilon.to_string(), "01001".repeat(21));
+        assert_eq!(puzzle.oxygen().to_string(), "10111".repeat(21));
+        assert_eq!(puzzle.c02().to_string(), "01010".repeat(21));
+        assert!(puzzle.part_1().is_err());
+
         Ok(())
     }
+
+    #[test]
+    fn column_counts() -> Result<(), Error> {
+        let counts = Puzzle::parse(INPUT)?.column_counts();
+
+        assert_eq!(counts, [7, 5, 8, 7, 5]);
+        Ok(())
+    }
+
+    #[test]
+    fn shared_bits() -> Result<(), Error> {
+        let puzzle = Puzzle::parse("1100\n1101\n1110")?;
+
+        assert_eq!(puzzle.oxygen().to_string(), "1101");
+        assert_eq!(puzzle.c02().to_string(), "1110");
+        Ok(())
+    }
+
+    #[test]
+    fn report() -> Result<(), Error> {
+        let puzzle = Puzzle::parse(INPUT)?;
+        let report = puzzle.report();
+        let decisions = |rounds: &[Round]| {
+            rounds
+                .iter()
+                .map(|round| (round.keep, round.decision, round.remaining.len()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(report.columns[1], Column { ones: 5, zeros: 7 });
+        assert_eq!(
+            decisions(&report.oxygen),
+            [
+                (true, Decision::Count, 7),
+                (false, Decision::Count, 4),
+                (true, Decision::Count, 3),
+                (true, Decision::Count, 2),
+                (true, Decision::Tie, 1),
+            ]
+        );
+        assert_eq!(
+            decisions(&report.c02),
+            [
+                (false, Decision::Count, 5),
+                (true, Decision::Count, 2),
+                (false, Decision::Tie, 1),
+            ]
+        );
+        assert_eq!(report.c02[2].remaining[0].to_string(), "01010");
+
+        Ok(())
+    }
+
+    #[test]
+    fn mismatched_width() {
+        assert!(Puzzle::parse("0101\n011\n").is_err());
+    }
 }
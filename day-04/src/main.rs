@@ -1,49 +1,169 @@
+use std::env;
+
 use util::*;
 
 #[derive(Debug)]
 struct Puzzle {
-    nums: Vec<u8>,
+    size: usize,
+    nums: Vec<u32>,
     cards: Vec<Card>,
 }
 
 impl Puzzle {
-    fn part_1(&self) -> Result<u64, Error> {
-        let mut cards = self.cards.clone();
+    fn simulate(&self, rules: &Rules) -> Game {
+        self.simulate_draws(rules, &self.nums)
+    }
 
-        for n in self.nums.iter().copied() {
-            for card in cards.iter_mut() {
-                card.mark(n);
+    fn simulate_draws(&self, rules: &Rules, nums: &[u32]) -> Game {
+        let groups = rules.groups(self.size);
+        let cells = self.size * self.size;
 
-                if card.is_complete() {
-                    return Ok(card.score(n));
-                }
+        // Win groups that each cell contributes to
+        let mut cell_groups = vec![Vec::new(); cells];
+
+        for (g, (_, group)) in groups.iter().enumerate() {
+            for cell in group {
+                cell_groups[*cell].push(g);
             }
         }
 
-        bail!("No winner")
-    }
+        // Every card and cell holding each number
+        let mut index = HashMap::<u32, Vec<(usize, usize)>>::new();
 
-    fn part_2(&self) -> Result<u64, Error> {
-        let mut cards = self.cards.clone();
-        let mut winners = vec![false; cards.len()];
-        let mut last = None;
+        for (i, card) in self.cards.iter().enumerate() {
+            for (cell, n) in card.nums.iter().enumerate() {
+                index.entry(*n).or_default().push((i, cell));
+            }
+        }
+
+        let mut marks = vec![false; self.cards.len() * cells];
+        let mut hits = vec![0; self.cards.len() * groups.len()];
+        let mut unmarked = self.cards.iter().map(Card::sum).collect::<Vec<_>>();
+        let mut winners = vec![false; self.cards.len()];
+        let mut wins = Vec::new();
 
-        for n in self.nums.iter().copied() {
-            for (i, card) in cards.iter_mut().enumerate() {
-                if winners[i] {
+        for (draw, n) in nums.iter().copied().enumerate() {
+            for (i, cell) in index.get(&n).into_iter().flatten().copied() {
+                let mark = &mut marks[i * cells + cell];
+
+                if winners[i] || *mark {
                     continue;
                 }
 
-                card.mark(n);
+                *mark = true;
+                unmarked[i] -= u64::from(n);
+
+                let mut line = None;
+
+                for g in cell_groups[cell].iter().copied() {
+                    let hit = &mut hits[i * groups.len() + g];
 
-                if card.is_complete() {
+                    *hit += 1;
+
+                    if line.is_none() && *hit == groups[g].1.len() {
+                        line = Some(groups[g].0);
+                    }
+                }
+
+                if let Some(line) = line {
                     winners[i] = true;
-                    last = Some(card.score(n));
+                    wins.push(Win {
+                        card: i,
+                        draw,
+                        number: n,
+                        line,
+                        score: unmarked[i] * u64::from(n),
+                    });
                 }
             }
         }
 
-        last.context("No winner")
+        let losers = winners
+            .into_iter()
+            .enumerate()
+            .filter(|(_, won)| !won)
+            .map(|(i, _)| i)
+            .collect();
+
+        Game { wins, losers }
+    }
+
+    fn play(&self, rules: &Rules, winner: Winner) -> Result<u64, Error> {
+        let game = self.simulate(rules);
+        let win = match winner {
+            Winner::First => game.wins.first(),
+            Winner::Last => game.wins.last(),
+        };
+
+        Ok(win.context("No winner")?.score)
+    }
+
+    // Cards with distinct numbers below `max`, drawn in a random order
+    fn generate(rng: &mut Rng, cards: usize, size: usize, max: u32) -> Result<Self, Error> {
+        if size == 0 || size * size > max as usize {
+            bail!(
+                "Cannot fill a {0}x{0} card with numbers below {1}",
+                size,
+                max
+            );
+        }
+
+        let mut nums = (0..max).collect::<Vec<_>>();
+        let cards = (0..cards)
+            .map(|_| {
+                rng.shuffle(&mut nums);
+
+                Card {
+                    size,
+                    nums: nums[..size * size].to_vec(),
+                }
+            })
+            .collect();
+
+        rng.shuffle(&mut nums);
+
+        Ok(Self { size, nums, cards })
+    }
+
+    // Plays `games` games with the draws shuffled each time
+    fn fairness(&self, rules: &Rules, rng: &mut Rng, games: usize) -> Fairness {
+        let mut nums = self.nums.clone();
+        let mut cards = vec![CardStats::default(); self.cards.len()];
+
+        for _ in 0..games {
+            rng.shuffle(&mut nums);
+
+            let game = self.simulate_draws(rules, &nums);
+            let first = match game.wins.first() {
+                Some(win) => win.draw,
+                None => continue,
+            };
+
+            // Cards completing on the same draw share the win
+            let winners = game.wins.iter().take_while(|win| win.draw == first).count();
+
+            for (i, win) in game.wins.iter().enumerate() {
+                let stats = &mut cards[win.card];
+
+                if i < winners {
+                    stats.wins += 1.0 / winners as f64;
+                }
+
+                stats.completed += 1;
+                stats.draws += (win.draw + 1) as f64;
+                stats.draws_squared += ((win.draw + 1) as f64).powi(2);
+            }
+        }
+
+        Fairness { games, cards }
+    }
+
+    fn part_1(&self) -> Result<u64, Error> {
+        self.play(&Rules::default(), Winner::First)
+    }
+
+    fn part_2(&self) -> Result<u64, Error> {
+        self.play(&Rules::default(), Winner::Last)
     }
 }
 
@@ -55,64 +175,336 @@ impl FromStr for Puzzle {
         let nums = l
             .trim()
             .split(',')
-            .map(u8::from_str)
+            .map(u32::from_str)
             .collect::<Result<_, _>>()?;
 
         let cards = r
             .trim()
             .split("\n\n")
             .map(Card::from_str)
-            .collect::<Result<_, _>>()?;
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let size = cards[0].size;
 
-        Ok(Self { nums, cards })
+        if cards.iter().any(|card| card.size != size) {
+            bail!("Cards must all be the same size");
+        }
+
+        Ok(Self { size, nums, cards })
+    }
+}
+
+impl fmt::Display for Puzzle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.nums.iter().join(","))?;
+
+        for card in &self.cards {
+            writeln!(f)?;
+            write!(f, "{}", card)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct CardStats {
+    // Fractional when several cards win on the same draw
+    wins: f64,
+    // Games where the card completed at all
+    completed: usize,
+    draws: f64,
+    draws_squared: f64,
+}
+
+// Both `None` for a card that never completed
+impl CardStats {
+    fn expected_draw(&self) -> Option<f64> {
+        (self.completed > 0).then(|| self.draws / self.completed as f64)
+    }
+
+    fn draw_std_dev(&self) -> Option<f64> {
+        let mean = self.expected_draw()?;
+
+        Some(
+            (self.draws_squared / self.completed as f64 - mean * mean)
+                .max(0.0)
+                .sqrt(),
+        )
     }
 }
 
 #[derive(Clone, Debug)]
-struct Card {
-    nums: [[u8; 5]; 5],
-    marks: [[bool; 5]; 5],
+struct Fairness {
+    games: usize,
+    cards: Vec<CardStats>,
 }
 
-impl Card {
-    fn mark(&mut self, n: u8) {
-        let point = self.nums.iter().enumerate().find_map(|(y, row)| {
-            row.iter()
-                .enumerate()
-                .find(|(_, v)| **v == n)
-                .map(|(x, _)| (x, y))
-        });
+impl Fairness {
+    fn probability(&self, card: usize) -> f64 {
+        self.cards[card].wins / self.games as f64
+    }
+}
+
+impl fmt::Display for Fairness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fair = 1.0 / self.cards.len() as f64;
+        let (min, max) = (0..self.cards.len())
+            .map(|i| self.probability(i))
+            .minmax()
+            .into_option()
+            .unwrap_or_default();
+
+        writeln!(f, "  card  win %    vs fair  mean draw  std dev")?;
+
+        let show = |n: Option<f64>| n.map_or("-".to_string(), |n| format!("{:.2}", n));
+
+        for (i, stats) in self.cards.iter().enumerate() {
+            let p = self.probability(i);
+
+            writeln!(
+                f,
+                "  {:>4}  {:>6.2}  {:>+7.2}  {:>9}  {:>7}",
+                i + 1,
+                p * 100.0,
+                (p - fair) * 100.0,
+                show(stats.expected_draw()),
+                show(stats.draw_std_dev()),
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Games:      {}", self.games)?;
+        writeln!(f, "Fair share: {:.2}%", fair * 100.0)?;
+        writeln!(f, "Min share:  {:.2}%", min * 100.0)?;
+        writeln!(f, "Max share:  {:.2}%", max * 100.0)
+    }
+}
 
-        if let Some((x, y)) = point {
-            self.marks[y][x] = true;
+// SplitMix64, seeded explicitly so that generated games are reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.0;
+
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        ((u128::from(self.next()) * n as u128) >> 64) as usize
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
         }
     }
+}
+
+#[derive(Clone, Debug)]
+struct Win {
+    card: usize,
+    // Index into the drawn numbers
+    draw: usize,
+    number: u32,
+    line: Line,
+    score: u64,
+}
 
-    fn is_complete(&self) -> bool {
-        self.marks.iter().enumerate().any(|(i, row)| {
-            if row.iter().all(|n| *n) {
-                return true;
+#[derive(Clone, Debug)]
+struct Game {
+    // In winning order
+    wins: Vec<Win>,
+    // Cards that never win
+    losers: Vec<usize>,
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  rank  card  draw  number  line           score")?;
+
+        for (rank, win) in self.wins.iter().enumerate() {
+            writeln!(
+                f,
+                "  {:>4}  {:>4}  {:>4}  {:>6}  {:<13}  {:>5}",
+                rank + 1,
+                win.card + 1,
+                win.draw + 1,
+                win.number,
+                win.line.to_string(),
+                win.score,
+            )?;
+        }
+
+        if !self.losers.is_empty() {
+            writeln!(
+                f,
+                "Never won: {}",
+                self.losers.iter().map(|i| i + 1).join(", ")
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Winner {
+    First,
+    Last,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Line {
+    Row(usize),
+    Column(usize),
+    Diagonal,
+    AntiDiagonal,
+    FullCard,
+    FourCorners,
+    X,
+}
+
+// Rows and columns are displayed one-based
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Line::Row(y) => write!(f, "row {}", y + 1),
+            Line::Column(x) => write!(f, "column {}", x + 1),
+            Line::Diagonal => write!(f, "diagonal"),
+            Line::AntiDiagonal => write!(f, "anti-diagonal"),
+            Line::FullCard => write!(f, "full card"),
+            Line::FourCorners => write!(f, "four corners"),
+            Line::X => write!(f, "x"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Pattern {
+    // Any complete row or column
+    Lines,
+    Diagonals,
+    FullCard,
+    FourCorners,
+    X,
+}
+
+impl Pattern {
+    // Each group is a set of cell indices that wins once every cell is marked
+    fn groups(&self, size: usize) -> Vec<(Line, Vec<usize>)> {
+        let diagonal = || (0..size).map(|i| i * size + i);
+        let anti_diagonal = || (0..size).map(|i| i * size + size - 1 - i);
+
+        match self {
+            Pattern::Lines => (0..size)
+                .map(|y| (Line::Row(y), (0..size).map(|x| y * size + x).collect()))
+                .chain(
+                    (0..size).map(|x| (Line::Column(x), (0..size).map(|y| y * size + x).collect())),
+                )
+                .collect(),
+            Pattern::Diagonals => vec![
+                (Line::Diagonal, diagonal().collect()),
+                (Line::AntiDiagonal, anti_diagonal().collect()),
+            ],
+            Pattern::FullCard => vec![(Line::FullCard, (0..size * size).collect())],
+            Pattern::FourCorners => {
+                let last = size - 1;
+                let cells = [0, last, last * size, last * size + last];
+
+                vec![(Line::FourCorners, cells.into_iter().unique().collect())]
             }
+            Pattern::X => vec![(
+                Line::X,
+                diagonal().chain(anti_diagonal()).unique().collect(),
+            )],
+        }
+    }
+}
 
-            self.marks.iter().map(|row| row[i]).all(|n| n)
-        })
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pattern = match s.trim() {
+            "lines" => Pattern::Lines,
+            "diagonals" => Pattern::Diagonals,
+            "full" => Pattern::FullCard,
+            "corners" => Pattern::FourCorners,
+            "x" => Pattern::X,
+            _ => bail!("Unknown win pattern: {}", s),
+        };
+
+        Ok(pattern)
     }
+}
+
+#[derive(Clone, Debug)]
+struct Rules {
+    patterns: Vec<Pattern>,
+}
 
-    fn score(&self, n: u8) -> u64 {
-        let sum = self
-            .marks
+impl Rules {
+    fn groups(&self, size: usize) -> Vec<(Line, Vec<usize>)> {
+        self.patterns
             .iter()
-            .enumerate()
-            .flat_map(|(y, row)| {
-                row.iter()
-                    .enumerate()
-                    .filter(|(_, n)| !*n)
-                    .map(move |(x, _)| (x, y))
-            })
-            .map(|(x, y)| u64::from(self.nums[y][x]))
-            .sum::<u64>();
+            .flat_map(|pattern| pattern.groups(size))
+            .collect()
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            patterns: vec![Pattern::Lines],
+        }
+    }
+}
+
+impl FromStr for Rules {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let patterns = s
+            .split(',')
+            .map(Pattern::from_str)
+            .collect::<Result<_, _>>()?;
 
-        sum * u64::from(n)
+        Ok(Self { patterns })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Card {
+    size: usize,
+    // Row major
+    nums: Vec<u32>,
+}
+
+impl Card {
+    fn sum(&self) -> u64 {
+        self.nums.iter().copied().map(u64::from).sum()
+    }
+}
+
+impl fmt::Display for Card {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.nums.iter().map(|n| n.to_string().len()).max();
+
+        for row in self.nums.chunks(self.size) {
+            let row = row
+                .iter()
+                .map(|n| format!("{:>1$}", n, width.unwrap_or_default()));
+
+            writeln!(f, "{}", row.format(" "))?;
+        }
+
+        Ok(())
     }
 }
 
@@ -120,31 +512,86 @@ impl FromStr for Card {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let nums = s
+        let rows = s
             .trim()
             .lines()
-            .map(|line| -> Result<_, Error> {
-                line.trim()
-                    .split_whitespace()
-                    .map(u8::from_str)
-                    .collect::<Result<Vec<_>, _>>()?
-                    .try_into()
-                    .map_err(|_| Error::msg("Incorrect column length"))
+            .map(|line| {
+                line.split_whitespace()
+                    .map(u32::from_str)
+                    .collect::<Result<Vec<_>, _>>()
             })
-            .collect::<Result<Vec<_>, _>>()?
-            .try_into()
-            .map_err(|_| Error::msg("Incorrect row size"))?;
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let size = rows.len();
+
+        if rows.iter().any(|row| row.len() != size) {
+            bail!("Incorrect column length");
+        }
 
         Ok(Self {
-            nums,
-            marks: Default::default(),
+            size,
+            nums: rows.into_iter().flatten().collect(),
         })
     }
 }
 
+fn arg<T>(args: &mut impl Iterator<Item = String>, default: T) -> Result<T, Error>
+where
+    T: FromStr,
+    T::Err: Into<Error>,
+{
+    match args.next() {
+        Some(s) => T::from_str(&s).map_err(Into::into),
+        None => Ok(default),
+    }
+}
+
 fn main() -> Result<(), Error> {
+    let mut args = env::args().skip(1).peekable();
+
+    // `generate [cards] [size] [max] [seed]` writes a random puzzle input
+    if args.next_if(|arg| arg == "generate").is_some() {
+        let cards = arg(&mut args, 100)?;
+        let size = arg(&mut args, 5)?;
+        let max = arg(&mut args, 100)?;
+        let mut rng = Rng(arg(&mut args, 0)?);
+
+        print!("{}", Puzzle::generate(&mut rng, cards, size, max)?);
+
+        return Ok(());
+    }
+
     let puzzle = Puzzle::from_str(&read_stdin()?)?;
 
+    // `fairness [games] [seed] [rules]` replays the cards with shuffled draws
+    if args.next_if(|arg| arg == "fairness").is_some() {
+        let games = arg(&mut args, 1000)?;
+        let mut rng = Rng(arg(&mut args, 0)?);
+        let rules = arg(&mut args, Rules::default())?;
+
+        print!("{}", puzzle.fairness(&rules, &mut rng, games));
+
+        return Ok(());
+    }
+
+    let timeline = args.next_if(|arg| arg == "timeline").is_some();
+
+    // Optional comma separated list of win patterns, e.g. `lines,diagonals`
+    let rules = args.next().map(|s| Rules::from_str(&s)).transpose()?;
+
+    if timeline {
+        print!("{}", puzzle.simulate(&rules.unwrap_or_default()));
+
+        return Ok(());
+    }
+
+    if let Some(rules) = rules {
+        println!("First winner: {}", puzzle.play(&rules, Winner::First)?);
+        println!("Last winner: {}", puzzle.play(&rules, Winner::Last)?);
+
+        return Ok(());
+    }
+
     println!("Part 1: {}", puzzle.part_1()?);
     println!("Part 2: {}", puzzle.part_2()?);
 
@@ -188,4 +635,129 @@ mod test {
         assert_eq!(Puzzle::from_str(INPUT)?.part_2()?, 1924);
         Ok(())
     }
+
+    #[test]
+    fn diagonals() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str(INPUT)?;
+
+        // The third card completes its anti-diagonal on 2
+        assert_eq!(
+            puzzle.play(&Rules::from_str("lines,diagonals")?, Winner::First)?,
+            494
+        );
+        assert_eq!(
+            puzzle.play(&Rules::from_str("diagonals")?, Winner::First)?,
+            494
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn patterns() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str(
+            r#"
+            1,9,3,7,5,2,4,6,8
+
+            1 2 3
+            4 5 6
+            7 8 9
+        "#,
+        )?;
+        let play =
+            |rules| -> Result<_, Error> { puzzle.play(&Rules::from_str(rules)?, Winner::First) };
+
+        assert_eq!(puzzle.size, 3);
+        assert_eq!(play("lines")?, 18 * 2);
+        assert_eq!(play("diagonals")?, 20 * 5);
+        assert_eq!(play("corners")?, 25 * 7);
+        assert_eq!(play("x")?, 20 * 5);
+        assert_eq!(play("full")?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn timeline() -> Result<(), Error> {
+        let game = Puzzle::from_str(INPUT)?.simulate(&Rules::default());
+        let order = game
+            .wins
+            .iter()
+            .map(|win| (win.card, win.number, win.line))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            order,
+            [
+                (2, 24, Line::Row(0)),
+                (0, 16, Line::Row(2)),
+                (1, 13, Line::Column(2)),
+            ]
+        );
+        assert_eq!(game.wins[2].score, 1924);
+        assert!(game.losers.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn large_numbers() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str(
+            r#"
+            1000,70000,3
+
+            1000  256
+            70000   3
+
+             3    1000
+             9 4000000
+        "#,
+        )?;
+        let game = puzzle.simulate(&Rules::default());
+
+        assert_eq!(game.wins[0].card, 0);
+        assert_eq!(game.wins[0].line, Line::Column(0));
+        assert_eq!(game.wins[0].score, (256 + 3) * 70000);
+        assert_eq!(game.wins[1].line, Line::Row(0));
+        assert_eq!(game.wins[1].score, (9 + 4000000) * 3);
+        Ok(())
+    }
+
+    #[test]
+    fn generate() -> Result<(), Error> {
+        let mut rng = Rng(7);
+        let puzzle = Puzzle::generate(&mut rng, 20, 5, 75)?;
+        let parsed = Puzzle::from_str(&puzzle.to_string())?;
+
+        assert_eq!(parsed.nums, puzzle.nums);
+        assert_eq!(parsed.cards.len(), 20);
+        assert!(parsed
+            .cards
+            .iter()
+            .all(|card| card.nums.iter().unique().count() == 25));
+        assert!(Puzzle::generate(&mut rng, 1, 5, 24).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn fairness() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str(INPUT)?;
+        let fairness = puzzle.fairness(&Rules::default(), &mut Rng(1), 200);
+        let total = (0..3).map(|i| fairness.probability(i)).sum::<f64>();
+
+        assert!((total - 1.0).abs() < 1e-9);
+        assert!(fairness.cards.iter().all(|card| card.completed == 200));
+
+        // A card that never completes has no draw to average
+        let puzzle = Puzzle::from_str("1,2,3,4\n\n5 6\n7 8")?;
+        let fairness = puzzle.fairness(&Rules::default(), &mut Rng(1), 10);
+
+        assert_eq!(fairness.cards[0].expected_draw(), None);
+        assert!(fairness
+            .to_string()
+            .contains("     1    0.00  -100.00          -        -"));
+        Ok(())
+    }
+
+    #[test]
+    fn not_square() {
+        assert!(Card::from_str("1 2 3\n4 5 6").is_err());
+    }
 }
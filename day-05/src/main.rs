@@ -1,4 +1,4 @@
-use std::cmp::{Ord, Ordering};
+use std::{collections::BTreeSet, env};
 
 use util::*;
 
@@ -8,44 +8,66 @@ struct Puzzle {
 }
 
 impl Puzzle {
-    fn part_1(&self) -> usize {
-        let segments = self
+    fn overlaps(&self, raster: Raster) -> Result<usize, Error> {
+        let progressions = self
             .segments
             .iter()
-            .filter(|s| s.start.0 == s.end.0 || s.start.1 == s.end.1)
-            .cloned()
-            .collect();
-
-        let puzzle = Self { segments };
+            .map(|segment| segment.progression(raster))
+            .collect::<Result<Option<Vec<_>>, _>>()?;
 
-        puzzle.part_2()
+        match progressions {
+            Some(progressions) => Ok(sweep(&progressions)),
+            // Rasterised lines with other slopes are not evenly spaced
+            None => self.count_points(raster),
+        }
     }
 
-    fn part_2(&self) -> usize {
+    fn count_points(&self, raster: Raster) -> Result<usize, Error> {
         let mut counts = HashMap::<_, usize>::new();
-        let mut incr = |x, y| *counts.entry((x, y)).or_default() += 1;
-        let delta = |a: i64, b: i64| match a.cmp(&b) {
-            Ordering::Less => 1,
-            Ordering::Greater => -1,
-            Ordering::Equal => 0,
-        };
 
         for segment in &self.segments {
-            let i = delta(segment.start.0, segment.end.0);
-            let j = delta(segment.start.1, segment.end.1);
-            let (mut x, mut y) = segment.start;
-
-            while (x, y) != segment.end {
-                incr(x, y);
-                x += i;
-                y += j;
+            for point in segment.points(raster)? {
+                *counts.entry(point).or_default() += 1;
             }
+        }
+
+        Ok(counts.into_iter().filter(|(_, v)| *v > 1).count())
+    }
+
+    fn heatmap(&self, raster: Raster, window: Option<Window>) -> Result<Heatmap, Error> {
+        let window = match window {
+            Some(window) => window,
+            None => Window::bounds(&self.segments).context("Expected segments")?,
+        };
 
-            // Range is inclusive
-            incr(x, y);
+        let mut heatmap = Heatmap::new(window)?;
+
+        for segment in &self.segments {
+            for point in segment.points(raster)? {
+                if let Some(count) = heatmap.get_mut(point) {
+                    *count += 1;
+                }
+            }
         }
 
-        counts.into_iter().filter(|(_, v)| *v > 1).count()
+        Ok(heatmap)
+    }
+
+    fn part_1(&self) -> Result<usize, Error> {
+        let segments = self
+            .segments
+            .iter()
+            .filter(|s| s.start.0 == s.end.0 || s.start.1 == s.end.1)
+            .cloned()
+            .collect();
+
+        let puzzle = Self { segments };
+
+        puzzle.part_2()
+    }
+
+    fn part_2(&self) -> Result<usize, Error> {
+        self.overlaps(Raster::Diagonal)
     }
 }
 
@@ -69,6 +91,624 @@ struct Segment {
     end: (i64, i64),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Raster {
+    // Horizontal, vertical and 45 degree segments only; any other slope is an error
+    Diagonal,
+    // Integer rasterisation of any slope, one point per step along the major axis
+    Line,
+    // Only the integer points lying exactly on the segment
+    Lattice,
+}
+
+impl FromStr for Raster {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raster = match s {
+            "diagonal" => Raster::Diagonal,
+            "line" => Raster::Line,
+            "lattice" => Raster::Lattice,
+            _ => bail!("Unknown raster mode: {}", s),
+        };
+
+        Ok(raster)
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn cross(a: (i64, i64), b: (i64, i64)) -> i64 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+impl Segment {
+    fn delta(&self) -> (i64, i64) {
+        (self.end.0 - self.start.0, self.end.1 - self.start.1)
+    }
+
+    fn steps(&self, raster: Raster) -> Result<i64, Error> {
+        let (dx, dy) = self.delta();
+
+        let steps = match raster {
+            Raster::Diagonal if dx != 0 && dy != 0 && dx.abs() != dy.abs() => {
+                bail!("Segment {} is not horizontal, vertical or diagonal", self)
+            }
+            Raster::Diagonal | Raster::Line => dx.abs().max(dy.abs()),
+            Raster::Lattice => gcd(dx, dy),
+        };
+
+        Ok(steps)
+    }
+
+    // `None` if the rasterised points are not evenly spaced
+    fn progression(&self, raster: Raster) -> Result<Option<Progression>, Error> {
+        let (dx, dy) = self.delta();
+        let steps = self.steps(raster)?;
+
+        if steps == 0 {
+            return Ok(Some(Progression::new(self.start, (1, 0), 0)));
+        }
+
+        if dx % steps != 0 || dy % steps != 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(Progression::new(
+            self.start,
+            (dx / steps, dy / steps),
+            steps,
+        )))
+    }
+
+    fn points(&self, raster: Raster) -> Result<impl Iterator<Item = (i64, i64)>, Error> {
+        let (x, y) = self.start;
+        let (dx, dy) = self.delta();
+        let steps = self.steps(raster)?;
+
+        // Rounds half up; exact whenever the step lands on a lattice point
+        let div_round = move |n: i64| (2 * n + steps).div_euclid(2 * steps.max(1));
+
+        Ok((0..=steps).map(move |i| (x + div_round(i * dx), y + div_round(i * dy))))
+    }
+}
+
+// Evenly spaced points `start + i * step` for `i` in `0..=len`, with `step`
+// primitive and pointing towards positive `x`, or positive `y` if vertical
+#[derive(Clone, Copy, Debug)]
+struct Progression {
+    start: (i64, i64),
+    step: (i64, i64),
+    len: i64,
+}
+
+impl Progression {
+    fn new(start: (i64, i64), step: (i64, i64), len: i64) -> Self {
+        let end = (start.0 + step.0 * len, start.1 + step.1 * len);
+
+        if step.0 < 0 || (step.0 == 0 && step.1 < 0) {
+            Self {
+                start: end,
+                step: (-step.0, -step.1),
+                len,
+            }
+        } else {
+            Self { start, step, len }
+        }
+    }
+
+    fn end(&self) -> (i64, i64) {
+        self.point(self.len)
+    }
+
+    fn point(&self, i: i64) -> (i64, i64) {
+        (
+            self.start.0 + self.step.0 * i,
+            self.start.1 + self.step.1 * i,
+        )
+    }
+
+    // Identifies the infinite line through the progression; parallel lines
+    // share `step` and differ in the cross product of `step` with any point
+    fn line(&self) -> ((i64, i64), i64) {
+        (self.step, cross(self.step, self.start))
+    }
+
+    // Position of a point of the line, consecutive lattice points are one apart
+    fn position(&self, (x, y): (i64, i64)) -> i64 {
+        match self.step {
+            (0, _) => y,
+            (a, _) => x.div_euclid(a),
+        }
+    }
+
+    // Lattice point shared with a progression on a different line
+    fn intersection(&self, other: &Self) -> Option<(i64, i64)> {
+        let cross = |a: (i64, i64), b: (i64, i64)| {
+            i128::from(a.0) * i128::from(b.1) - i128::from(a.1) * i128::from(b.0)
+        };
+
+        let den = cross(self.step, other.step);
+
+        if den == 0 {
+            return None;
+        }
+
+        let offset = (other.start.0 - self.start.0, other.start.1 - self.start.1);
+        let t = cross(offset, other.step);
+        let s = cross(offset, self.step);
+
+        if t % den != 0 || s % den != 0 {
+            return None;
+        }
+
+        let (t, s) = (t / den, s / den);
+        let len = i128::from(self.len);
+        let other_len = i128::from(other.len);
+
+        if !(0..=len).contains(&t) || !(0..=other_len).contains(&s) {
+            return None;
+        }
+
+        Some(self.point(t as i64))
+    }
+
+    // Range of `cross(step, point)` over the points of the progression
+    fn span(&self, step: (i64, i64)) -> (i64, i64) {
+        let (a, b) = (cross(step, self.start), cross(step, self.end()));
+
+        (a.min(b), a.max(b))
+    }
+}
+
+// Inclusive, sorted and disjoint
+type Ranges = Vec<(i64, i64)>;
+
+// Positions covered by at least one and at least two intervals
+fn coverage(intervals: &[(i64, i64)]) -> (Ranges, Ranges) {
+    let mut events = intervals
+        .iter()
+        .flat_map(|(lo, hi)| [(*lo, 1), (hi + 1, -1)])
+        .collect::<Vec<_>>();
+
+    events.sort_unstable();
+
+    let mut union = Vec::new();
+    let mut double = Vec::new();
+    let mut union_start = 0;
+    let mut double_start = 0;
+    let mut depth = 0;
+    let mut events = events.into_iter().peekable();
+
+    while let Some((pos, delta)) = events.next() {
+        let before = depth;
+
+        depth += delta;
+
+        while let Some((_, delta)) = events.next_if(|(next, _)| *next == pos) {
+            depth += delta;
+        }
+
+        match (before, depth) {
+            (0, d) if d > 0 => union_start = pos,
+            (b, 0) if b > 0 => union.push((union_start, pos - 1)),
+            _ => {}
+        }
+
+        match (before >= 2, depth >= 2) {
+            (false, true) => double_start = pos,
+            (true, false) => double.push((double_start, pos - 1)),
+            _ => {}
+        }
+    }
+
+    (union, double)
+}
+
+fn contains(ranges: &Ranges, pos: i64) -> bool {
+    let i = ranges.partition_point(|(_, hi)| *hi < pos);
+
+    ranges.get(i).is_some_and(|(lo, _)| *lo <= pos)
+}
+
+// Prefix sums with point updates
+#[derive(Debug)]
+struct Fenwick {
+    tree: Vec<i64>,
+}
+
+impl Fenwick {
+    fn new(len: usize) -> Self {
+        Self {
+            tree: vec![0; len + 1],
+        }
+    }
+
+    fn add(&mut self, i: usize, delta: i64) {
+        let mut i = i + 1;
+
+        while i < self.tree.len() {
+            self.tree[i] += delta;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    // Sum of the first `i` slots
+    fn prefix(&self, mut i: usize) -> i64 {
+        let mut sum = 0;
+
+        while i > 0 {
+            sum += self.tree[i];
+            i -= i & i.wrapping_neg();
+        }
+
+        sum
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Event {
+    // Slot of a piece, added to or removed from the active ones
+    Update(usize, i64),
+    // Slots crossed by a query
+    Query(usize, usize),
+}
+
+// Lattice points shared by query progressions of the family with step `f`
+// and pieces of the family with step `g`. Lines of `f` have a fixed
+// `u = cross(f, point)` and lines of `g` a fixed `v = cross(g, point)`, so
+// in `(u, v)` the progressions are perpendicular bars, swept along `u`. The
+// points of a line of `f` share `v` modulo `cross(f, g)`, and a bar of `g`
+// only meets it at a lattice point if its `v` has the same residue
+#[derive(Debug)]
+struct Crossings {
+    f: (i64, i64),
+    g: (i64, i64),
+    // Met one by one instead, when there are too few pairs to sweep
+    points: Vec<(i64, i64)>,
+    // `(residue, v)` of the pieces, sorted
+    slots: Vec<(i64, i64)>,
+    events: Vec<(i64, Event)>,
+}
+
+impl Crossings {
+    const DIRECT: usize = 64;
+
+    fn new(f: (i64, i64), queries: &[Progression], g: (i64, i64), pieces: &[Progression]) -> Self {
+        if queries.len() * pieces.len() <= Self::DIRECT {
+            let points = queries
+                .iter()
+                .cartesian_product(pieces)
+                .filter_map(|(query, piece)| query.intersection(piece))
+                .collect();
+
+            return Self {
+                f,
+                g,
+                points,
+                slots: Vec::new(),
+                events: Vec::new(),
+            };
+        }
+
+        let modulus = cross(f, g).abs();
+        let slot = |v: i64| (v.rem_euclid(modulus), v);
+
+        let mut slots = pieces
+            .iter()
+            .map(|piece| slot(cross(g, piece.start)))
+            .collect::<Vec<_>>();
+
+        slots.sort_unstable();
+        slots.dedup();
+
+        let index = |slot| slots.partition_point(|s| *s < slot);
+        let mut events = Vec::with_capacity(2 * pieces.len() + queries.len());
+
+        for piece in pieces {
+            let i = index(slot(cross(g, piece.start)));
+            let (lo, hi) = piece.span(f);
+
+            events.push((lo, Event::Update(i, 1)));
+            events.push((hi + 1, Event::Update(i, -1)));
+        }
+
+        for query in queries {
+            let (lo, hi) = query.span(g);
+            let residue = lo.rem_euclid(modulus);
+            let range = (index((residue, lo)), index((residue, hi + 1)));
+
+            events.push((cross(f, query.start), Event::Query(range.0, range.1)));
+        }
+
+        // Removals, then additions, then queries at the same `u`
+        events.sort_unstable_by_key(|(u, event)| match event {
+            Event::Update(_, delta) => (*u, *delta),
+            Event::Query(..) => (*u, 2),
+        });
+
+        Self {
+            f,
+            g,
+            points: Vec::new(),
+            slots,
+            events,
+        }
+    }
+
+    fn count(&self) -> i64 {
+        let mut active = Fenwick::new(self.slots.len());
+        let mut count = self.points.len() as i64;
+
+        for (_, event) in &self.events {
+            match *event {
+                Event::Update(i, delta) => active.add(i, delta),
+                Event::Query(lo, hi) => count += active.prefix(hi) - active.prefix(lo),
+            }
+        }
+
+        count
+    }
+
+    fn for_each(&self, mut visit: impl FnMut((i64, i64))) {
+        let mut active = BTreeSet::new();
+
+        self.points.iter().copied().for_each(&mut visit);
+
+        for (u, event) in &self.events {
+            match *event {
+                Event::Update(i, 1) => {
+                    active.insert(i);
+                }
+                Event::Update(i, _) => {
+                    active.remove(&i);
+                }
+                Event::Query(lo, hi) => {
+                    for i in active.range(lo..hi) {
+                        visit(self.point(*u, self.slots[*i].1));
+                    }
+                }
+            }
+        }
+    }
+
+    // Inverse of `point -> (cross(f, point), cross(g, point))`
+    fn point(&self, u: i64, v: i64) -> (i64, i64) {
+        let (f, g) = (self.f, self.g);
+        let (u, v) = (i128::from(u), i128::from(v));
+        let det = i128::from(cross(f, g));
+        let x = (u * i128::from(g.0) - v * i128::from(f.0)) / det;
+        let y = (u * i128::from(g.1) - v * i128::from(f.1)) / det;
+
+        (x as i64, y as i64)
+    }
+}
+
+// Merged segments on one line, as positions along it
+#[derive(Debug)]
+struct Line {
+    reference: Progression,
+    union: Ranges,
+    double: Ranges,
+}
+
+impl Line {
+    fn progressions<'a>(&'a self, ranges: &'a Ranges) -> impl Iterator<Item = Progression> + 'a {
+        let base = self.reference.position(self.reference.start);
+
+        ranges.iter().map(move |(lo, hi)| {
+            let start = self.reference.point(lo - base);
+
+            Progression::new(start, self.reference.step, hi - lo)
+        })
+    }
+}
+
+// Parallel lines, by the key of `Progression::line`
+#[derive(Debug)]
+struct Family {
+    step: (i64, i64),
+    lines: HashMap<i64, Line>,
+    // A bit per key modulo a power of two, about 64 bits per line, so most
+    // points off every line of the family are rejected without hashing
+    keys: Vec<u64>,
+    pieces: Vec<Progression>,
+    doubles: Vec<Progression>,
+}
+
+impl Family {
+    fn new(step: (i64, i64), lines: Vec<(i64, Line)>) -> Self {
+        let mut keys = vec![0; lines.len().next_power_of_two()];
+        let mut pieces = Vec::new();
+        let mut doubles = Vec::new();
+
+        for (key, line) in &lines {
+            let (i, mask) = Self::key_bit(&keys, *key);
+
+            keys[i] |= mask;
+            pieces.extend(line.progressions(&line.union));
+            doubles.extend(line.progressions(&line.double));
+        }
+
+        Self {
+            step,
+            lines: lines.into_iter().collect(),
+            keys,
+            pieces,
+            doubles,
+        }
+    }
+
+    fn key_bit(keys: &[u64], key: i64) -> (usize, u64) {
+        let bit = key as usize & (64 * keys.len() - 1);
+
+        (bit / 64, 1 << (bit % 64))
+    }
+
+    // Whether a merged piece covers the point, and whether it is covered twice
+    fn covers(&self, point: (i64, i64)) -> (bool, bool) {
+        let key = cross(self.step, point);
+        let (i, mask) = Self::key_bit(&self.keys, key);
+
+        if self.keys[i] & mask == 0 {
+            return (false, false);
+        }
+
+        match self.lines.get(&key) {
+            Some(line) => {
+                let pos = line.reference.position(point);
+
+                (contains(&line.union, pos), contains(&line.double, pos))
+            }
+            None => (false, false),
+        }
+    }
+}
+
+// Collinear overlaps are merged as intervals along each line, and lines
+// are grouped into families of parallel ones. Crossings between two
+// families are counted with a Fenwick sweep, so a point where `m` lines
+// meet is counted `m choose 2` times. Every three families have two in
+// the same group of a split in two, so walking the crossings within each
+// group finds the points where three or more lines meet, which are then
+// corrected one by one. Only the walk grows with the crossings, visiting
+// about a third of them for diagonal segments with a cheap check each;
+// the rest is O(n log n) per pair of families, which lattice segments in
+// many directions make numerous
+fn sweep(progressions: &[Progression]) -> usize {
+    let mut lines = HashMap::<_, Vec<_>>::new();
+
+    for progression in progressions {
+        let lo = progression.position(progression.start);
+
+        lines
+            .entry(progression.line())
+            .or_default()
+            .push((*progression, (lo, lo + progression.len)));
+    }
+
+    let mut families = HashMap::<_, Vec<_>>::new();
+
+    for ((step, key), segments) in lines {
+        let (reference, _) = segments[0];
+        let intervals = segments.iter().map(|(_, i)| *i).collect::<Vec<_>>();
+        let (union, double) = coverage(&intervals);
+
+        families.entry(step).or_default().push((
+            key,
+            Line {
+                reference,
+                union,
+                double,
+            },
+        ));
+    }
+
+    let families = families
+        .into_iter()
+        .sorted_by_key(|(step, _)| *step)
+        .map(|(step, lines)| Family::new(step, lines))
+        .collect::<Vec<_>>();
+
+    // Points covered twice along their line, plus every pair of lines that
+    // meet, less those double points where another line also crosses
+    let mut total = families
+        .iter()
+        .flat_map(|family| &family.doubles)
+        .map(|double| double.len + 1)
+        .sum::<i64>();
+
+    let mut counts = vec![vec![0; families.len()]; families.len()];
+
+    for (i, f) in families.iter().enumerate() {
+        for (j, g) in families.iter().enumerate() {
+            if i == j {
+                continue;
+            }
+
+            if i < j {
+                counts[i][j] = Crossings::new(f.step, &f.pieces, g.step, &g.pieces).count();
+                counts[j][i] = counts[i][j];
+                total += counts[i][j];
+            }
+
+            total -= Crossings::new(f.step, &f.doubles, g.step, &g.pieces).count();
+        }
+    }
+
+    // Splits greedily so that few crossings lie within a group
+    let mut groups = Vec::new();
+
+    for row in &counts {
+        let within = |group| {
+            groups
+                .iter()
+                .zip(row)
+                .filter(|(g, _)| **g == group)
+                .map(|(_, count)| count)
+                .sum::<i64>()
+        };
+
+        let group = within(false) > within(true);
+
+        groups.push(group);
+    }
+
+    let walked = (0..families.len())
+        .tuple_combinations()
+        .filter(|(i, j)| groups[*i] == groups[*j])
+        .collect::<Vec<_>>();
+
+    for (n, (i, j)) in walked.iter().copied().enumerate() {
+        let (f, g) = (&families[i], &families[j]);
+
+        Crossings::new(f.step, &f.pieces, g.step, &g.pieces).for_each(|point| {
+            let crowded =
+                (0..families.len()).any(|k| k != i && k != j && families[k].covers(point).0);
+
+            if !crowded {
+                return;
+            }
+
+            let covers = families
+                .iter()
+                .map(|family| family.covers(point))
+                .collect::<Vec<_>>();
+
+            // Corrected once, from the first walked pair meeting there
+            if walked[..n]
+                .iter()
+                .any(|(a, b)| covers[*a].0 && covers[*b].0)
+            {
+                return;
+            }
+
+            let lines = covers.iter().filter(|(on, _)| *on).count() as i64;
+            let doubles = covers.iter().filter(|(_, double)| *double).count() as i64;
+
+            total += 1 - lines * (lines - 1) / 2 + doubles * (lines - 2);
+        });
+    }
+
+    total as usize
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{},{} -> {},{}",
+            self.start.0, self.start.1, self.end.0, self.end.1
+        )
+    }
+}
+
 impl FromStr for Segment {
     type Err = Error;
 
@@ -88,11 +728,175 @@ impl FromStr for Segment {
     }
 }
 
+// Inclusive crop window
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Window {
+    min: (i64, i64),
+    max: (i64, i64),
+}
+
+impl Window {
+    fn bounds(segments: &[Segment]) -> Option<Self> {
+        let points = segments.iter().flat_map(|s| [s.start, s.end]);
+        let (min_x, max_x) = points.clone().map(|(x, _)| x).minmax().into_option()?;
+        let (min_y, max_y) = points.map(|(_, y)| y).minmax().into_option()?;
+
+        Some(Self {
+            min: (min_x, min_y),
+            max: (max_x, max_y),
+        })
+    }
+
+    fn width(&self) -> usize {
+        (self.max.0 - self.min.0 + 1) as usize
+    }
+
+    fn height(&self) -> usize {
+        (self.max.1 - self.min.1 + 1) as usize
+    }
+}
+
+impl FromStr for Window {
+    type Err = Error;
+
+    // `x0,y0,x1,y1`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (x0, y0, x1, y1) = s
+            .split(',')
+            .map(i64::from_str)
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .collect_tuple()
+            .context("Expected x0,y0,x1,y1")?;
+
+        Ok(Self {
+            min: (x0.min(x1), y0.min(y1)),
+            max: (x0.max(x1), y0.max(y1)),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Heatmap {
+    window: Window,
+    // Row major
+    counts: Vec<u32>,
+}
+
+impl Heatmap {
+    // Guards against allocating the full plane for far apart segments
+    const MAX_CELLS: usize = 1 << 28;
+
+    fn new(window: Window) -> Result<Self, Error> {
+        let cells = window.width().saturating_mul(window.height());
+
+        if cells > Self::MAX_CELLS {
+            bail!("Heatmap of {} cells is too large, try a crop window", cells);
+        }
+
+        Ok(Self {
+            window,
+            counts: vec![0; cells],
+        })
+    }
+
+    fn get_mut(&mut self, (x, y): (i64, i64)) -> Option<&mut u32> {
+        let Window { min, max } = self.window;
+
+        if x < min.0 || x > max.0 || y < min.1 || y > max.1 {
+            return None;
+        }
+
+        let i = (y - min.1) as usize * self.window.width() + (x - min.0) as usize;
+
+        self.counts.get_mut(i)
+    }
+
+    fn points(&self) -> impl Iterator<Item = ((i64, i64), u32)> + '_ {
+        let width = self.window.width();
+        let (x0, y0) = self.window.min;
+
+        self.counts.iter().enumerate().map(move |(i, count)| {
+            let x = x0 + (i % width) as i64;
+            let y = y0 + (i / width) as i64;
+
+            ((x, y), *count)
+        })
+    }
+
+    // Black for empty cells, blue for single coverage and red through
+    // yellow for overlaps, scaled to the densest cell
+    fn color(count: u32, max: u32) -> [u8; 3] {
+        match count {
+            0 => [0, 0, 0],
+            1 => [32, 64, 160],
+            _ => {
+                let t = (count - 2) as f64 / max.saturating_sub(2).max(1) as f64;
+
+                [255, (t * 255.0) as u8, 0]
+            }
+        }
+    }
+
+    fn write_ppm(&self, w: &mut impl io::Write) -> io::Result<()> {
+        let max = self.counts.iter().copied().max().unwrap_or_default();
+
+        write!(
+            w,
+            "P6\n{} {}\n255\n",
+            self.window.width(),
+            self.window.height()
+        )?;
+
+        for count in &self.counts {
+            w.write_all(&Self::color(*count, max))?;
+        }
+
+        Ok(())
+    }
+
+    // Only covered cells are listed
+    fn write_csv(&self, w: &mut impl io::Write) -> io::Result<()> {
+        writeln!(w, "x,y,count")?;
+
+        for ((x, y), count) in self.points().filter(|(_, count)| *count > 0) {
+            writeln!(w, "{},{},{}", x, y, count)?;
+        }
+
+        Ok(())
+    }
+}
+
 fn main() -> Result<(), Error> {
     let puzzle = Puzzle::from_str(&read_stdin()?)?;
+    let mut args = env::args().skip(1).peekable();
+
+    // `heatmap <ppm|csv> [raster] [x0,y0,x1,y1]` writes the coverage to stdout
+    if args.next_if(|arg| arg == "heatmap").is_some() {
+        let format = args.next().context("Expected heatmap format")?;
+        let raster = args.next().map(|s| Raster::from_str(&s)).transpose()?;
+        let window = args.next().map(|s| Window::from_str(&s)).transpose()?;
+        let heatmap = puzzle.heatmap(raster.unwrap_or(Raster::Diagonal), window)?;
+        let mut stdout = io::BufWriter::new(io::stdout().lock());
+
+        match format.as_str() {
+            "ppm" => heatmap.write_ppm(&mut stdout)?,
+            "csv" => heatmap.write_csv(&mut stdout)?,
+            _ => bail!("Unknown heatmap format: {}", format),
+        }
+
+        return Ok(());
+    }
+
+    // Optional raster mode for segments of any slope, `line` or `lattice`
+    if let Some(raster) = args.next() {
+        println!("Overlaps: {}", puzzle.overlaps(Raster::from_str(&raster)?)?);
+
+        return Ok(());
+    }
 
-    println!("Part 1: {}", puzzle.part_1());
-    println!("Part 2: {}", puzzle.part_2());
+    println!("Part 1: {}", puzzle.part_1()?);
+    println!("Part 2: {}", puzzle.part_2()?);
 
     Ok(())
 }
@@ -116,13 +920,115 @@ mod test {
 
     #[test]
     fn part_1() -> Result<(), Error> {
-        assert_eq!(Puzzle::from_str(INPUT)?.part_1(), 5);
+        assert_eq!(Puzzle::from_str(INPUT)?.part_1()?, 5);
         Ok(())
     }
 
     #[test]
     fn part_2() -> Result<(), Error> {
-        assert_eq!(Puzzle::from_str(INPUT)?.part_2(), 12);
+        assert_eq!(Puzzle::from_str(INPUT)?.part_2()?, 12);
+        Ok(())
+    }
+
+    #[test]
+    fn arbitrary_slope() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str("0,0 -> 4,2\n0,1 -> 4,1\n4,0 -> 0,2")?;
+
+        assert!(puzzle.part_2().is_err());
+        assert_eq!(puzzle.overlaps(Raster::Line)?, 3);
+        assert_eq!(puzzle.overlaps(Raster::Lattice)?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn points() -> Result<(), Error> {
+        let segment = Segment::from_str("0,0 -> 6,-4")?;
+        let line = segment.points(Raster::Line)?.collect::<Vec<_>>();
+        let lattice = segment.points(Raster::Lattice)?.collect::<Vec<_>>();
+
+        assert_eq!(
+            line,
+            [(0, 0), (1, -1), (2, -1), (3, -2), (4, -3), (5, -3), (6, -4)]
+        );
+        assert_eq!(lattice, [(0, 0), (3, -2), (6, -4)]);
+        Ok(())
+    }
+
+    #[test]
+    fn sweep_matches_points() -> Result<(), Error> {
+        let mut seed = 1u64;
+        let mut rand = |n: i64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            ((seed >> 33) % n as u64) as i64
+        };
+
+        for raster in [Raster::Diagonal, Raster::Lattice] {
+            for _ in 0..20 {
+                let segments = (0..40)
+                    .map(|_| {
+                        let start = (rand(30), rand(30));
+                        let len = rand(12);
+                        let (dx, dy) = match raster {
+                            Raster::Diagonal => [(1, 0), (0, 1), (1, 1), (1, -1)][rand(4) as usize],
+                            _ => (rand(7) - 3, rand(7) - 3),
+                        };
+
+                        Segment {
+                            start,
+                            end: (start.0 + dx * len, start.1 + dy * len),
+                        }
+                    })
+                    .collect();
+
+                let puzzle = Puzzle { segments };
+
+                assert_eq!(puzzle.overlaps(raster)?, puzzle.count_points(raster)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn crowded_points() -> Result<(), Error> {
+        // Doubled lines crossing, and four families meeting at 5,5
+        let puzzle = Puzzle::from_str(
+            "0,5 -> 9,5\n0,5 -> 9,5\n5,0 -> 5,9\n5,0 -> 5,9\n0,0 -> 9,9\n0,10 -> 10,0\n2,2 -> 8,8",
+        )?;
+
+        assert_eq!(puzzle.part_2()?, puzzle.count_points(Raster::Diagonal)?);
+        assert_eq!(puzzle.part_2()?, 25);
+        Ok(())
+    }
+
+    #[test]
+    fn heatmap() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str(INPUT)?;
+        let heatmap = puzzle.heatmap(Raster::Diagonal, None)?;
+        let overlaps = heatmap.counts.iter().filter(|c| **c > 1).count();
+
+        assert_eq!(heatmap.window.width(), 10);
+        assert_eq!(heatmap.window.height(), 10);
+        assert_eq!(overlaps, 12);
+
+        let window = Window::from_str("0,9,2,8")?;
+        let mut csv = Vec::new();
+
+        puzzle
+            .heatmap(Raster::Diagonal, Some(window))?
+            .write_csv(&mut csv)?;
+
+        assert_eq!(
+            String::from_utf8(csv)?,
+            "x,y,count\n0,8,1\n0,9,2\n1,9,2\n2,9,2\n"
+        );
+
+        let mut ppm = Vec::new();
+
+        heatmap.write_ppm(&mut ppm)?;
+
+        assert!(ppm.starts_with(b"P6\n10 10\n255\n"));
+        assert_eq!(ppm.len(), 13 + 10 * 10 * 3);
         Ok(())
     }
 }
@@ -1,27 +1,81 @@
+use std::{collections::BTreeMap, env};
+
 use util::*;
 
 #[derive(Debug)]
 struct Puzzle {
-    fish: [usize; 9],
+    // Number of fish with each timer value, kept sparse until the lifecycle
+    // bounds the timers
+    fish: BTreeMap<usize, u128>,
 }
 
 impl Puzzle {
-    fn days(&self, n: usize) -> usize {
-        let mut fish = self.fish;
+    fn population(
+        &self,
+        lifecycle: &Lifecycle,
+        days: u64,
+        modulus: Option<u64>,
+    ) -> Result<u128, Error> {
+        if modulus == Some(0) {
+            bail!("Modulus must be positive");
+        }
+
+        let modulus = modulus.map(u128::from);
+
+        let fish = self.timers(lifecycle)?;
+        let fish = lifecycle
+            .transition()
+            .pow(days, modulus)?
+            .apply(&fish, modulus)?;
+
+        fish.into_iter()
+            .try_fold(0, |sum, n| mul_add(sum, n, 1, modulus))
+    }
+
+    // Fish counts for every timer value in the lifecycle
+    fn timers(&self, lifecycle: &Lifecycle) -> Result<Vec<u128>, Error> {
+        let states = lifecycle.states();
+
+        let mut fish = vec![0; states];
+
+        for (timer, n) in &self.fish {
+            if *timer >= states {
+                bail!(
+                    "Fish timer {} exceeds the lifecycle of {} days",
+                    timer,
+                    states
+                );
+            }
 
-        for i in 0..n {
-            fish[(i + 7) % 9] += fish[i % 9];
+            fish[*timer] = *n;
         }
 
-        fish.into_iter().sum()
+        Ok(fish)
     }
 
-    fn part_1(&self) -> usize {
-        self.days(80)
+    // Stepped one day at a time, starting with the initial fish on day 0
+    fn history(&self, lifecycle: &Lifecycle, days: usize) -> Result<History, Error> {
+        let transition = lifecycle.transition();
+        let mut fish = self.timers(lifecycle)?;
+        let mut history = History::default();
+
+        for day in 0..=days {
+            if day > 0 {
+                fish = transition.apply(&fish, None)?;
+            }
+
+            history.push(fish.clone())?;
+        }
+
+        Ok(history)
     }
 
-    fn part_2(&self) -> usize {
-        self.days(256)
+    fn part_1(&self) -> Result<u128, Error> {
+        self.population(&Lifecycle::default(), 80, None)
+    }
+
+    fn part_2(&self) -> Result<u128, Error> {
+        self.population(&Lifecycle::default(), 256, None)
     }
 }
 
@@ -29,21 +83,271 @@ impl FromStr for Puzzle {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut fish = [0; 9];
+        let mut fish = BTreeMap::new();
 
         for n in s.trim().split(',') {
-            fish[usize::from_str(n)?] += 1;
+            *fish.entry(usize::from_str(n)?).or_insert(0) += 1;
         }
 
         Ok(Self { fish })
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Lifecycle {
+    // Days between an adult spawning
+    period: usize,
+    // Extra days before a newborn starts its first period
+    delay: usize,
+}
+
+impl Lifecycle {
+    fn states(&self) -> usize {
+        self.period + self.delay
+    }
+
+    // Moves the count for each timer value ahead by one day
+    fn transition(&self) -> Matrix {
+        let states = self.states();
+        let mut matrix = Matrix::zero(states);
+
+        for timer in 1..states {
+            matrix[(timer - 1, timer)] = 1;
+        }
+
+        matrix[(self.period - 1, 0)] += 1;
+        matrix[(states - 1, 0)] += 1;
+
+        matrix
+    }
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            period: 7,
+            delay: 2,
+        }
+    }
+}
+
+impl FromStr for Lifecycle {
+    type Err = Error;
+
+    // `period,delay`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (period, delay) = s.split_once(',').context("Expected period,delay")?;
+        let period = usize::from_str(period)?;
+        let delay = usize::from_str(delay)?;
+
+        if period == 0 {
+            bail!("Period must be positive");
+        }
+
+        Ok(Self { period, delay })
+    }
+}
+
+// `acc + a * b`, reduced if a modulus is given and checked otherwise
+fn mul_add(acc: u128, a: u128, b: u128, modulus: Option<u128>) -> Result<u128, Error> {
+    match modulus {
+        // Operands are already reduced below a `u64` modulus and cannot overflow
+        Some(m) => Ok((acc + a * b % m) % m),
+        None => a
+            .checked_mul(b)
+            .and_then(|product| product.checked_add(acc))
+            .context("Population overflows, try a modulus"),
+    }
+}
+
+// Square matrix, indexed by `(row, column)`
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Matrix {
+    size: usize,
+    cells: Vec<u128>,
+}
+
+impl Matrix {
+    fn zero(size: usize) -> Self {
+        Self {
+            size,
+            cells: vec![0; size * size],
+        }
+    }
+
+    fn identity(size: usize) -> Self {
+        let mut matrix = Self::zero(size);
+
+        for i in 0..size {
+            matrix[(i, i)] = 1;
+        }
+
+        matrix
+    }
+
+    fn mul(&self, other: &Self, modulus: Option<u128>) -> Result<Self, Error> {
+        let mut matrix = Self::zero(self.size);
+
+        for row in 0..self.size {
+            for col in 0..self.size {
+                matrix[(row, col)] = (0..self.size).try_fold(0, |acc, i| {
+                    mul_add(acc, self[(row, i)], other[(i, col)], modulus)
+                })?;
+            }
+        }
+
+        Ok(matrix)
+    }
+
+    // Exponentiation by squaring
+    fn pow(&self, mut exp: u64, modulus: Option<u128>) -> Result<Self, Error> {
+        let mut base = self.clone();
+        let mut result = Self::identity(self.size);
+
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result.mul(&base, modulus)?;
+            }
+
+            exp >>= 1;
+
+            // Skip the final square, it may overflow without being used
+            if exp > 0 {
+                base = base.mul(&base, modulus)?;
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn apply(&self, v: &[u128], modulus: Option<u128>) -> Result<Vec<u128>, Error> {
+        (0..self.size)
+            .map(|row| {
+                v.iter()
+                    .enumerate()
+                    .try_fold(0, |acc, (i, n)| mul_add(acc, self[(row, i)], *n, modulus))
+            })
+            .collect()
+    }
+}
+
+impl ops::Index<(usize, usize)> for Matrix {
+    type Output = u128;
+
+    fn index(&self, (row, col): (usize, usize)) -> &Self::Output {
+        &self.cells[row * self.size + col]
+    }
+}
+
+impl ops::IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (row, col): (usize, usize)) -> &mut Self::Output {
+        &mut self.cells[row * self.size + col]
+    }
+}
+
+#[derive(Debug, Default)]
+struct History {
+    // Fish counts by timer value for each day
+    days: Vec<Vec<u128>>,
+    totals: Vec<u128>,
+}
+
+impl History {
+    // Rows in the chart before days are sampled
+    const CHART_ROWS: usize = 40;
+    const CHART_WIDTH: usize = 60;
+
+    fn push(&mut self, fish: Vec<u128>) -> Result<(), Error> {
+        let total = fish
+            .iter()
+            .try_fold(0u128, |sum, n| sum.checked_add(*n))
+            .context("Population overflows")?;
+
+        self.days.push(fish);
+        self.totals.push(total);
+
+        Ok(())
+    }
+
+    fn write_csv(&self, w: &mut impl io::Write) -> io::Result<()> {
+        let states = self.days.first().map_or(0, Vec::len);
+
+        write!(w, "day")?;
+
+        for timer in 0..states {
+            write!(w, ",t{}", timer)?;
+        }
+
+        writeln!(w, ",total")?;
+
+        for (day, (fish, total)) in self.days.iter().zip(&self.totals).enumerate() {
+            writeln!(w, "{},{},{}", day, fish.iter().join(","), total)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Log scaled totals, sampled to fit
+impl fmt::Display for History {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let max = self.totals.iter().copied().max().unwrap_or_default();
+        let scale = (max as f64).log10().max(1.0);
+        let step = self.totals.len().div_ceil(Self::CHART_ROWS).max(1);
+        let width = (self.totals.len() - 1).to_string().len();
+
+        for (day, total) in self.totals.iter().enumerate().step_by(step) {
+            let len =
+                ((*total as f64).log10().max(0.0) / scale * Self::CHART_WIDTH as f64) as usize;
+
+            writeln!(
+                f,
+                "{:>width$} |{:<chart$}| {}",
+                day,
+                "#".repeat(len),
+                total,
+                width = width,
+                chart = Self::CHART_WIDTH,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
 fn main() -> Result<(), Error> {
     let puzzle = Puzzle::from_str(&read_stdin()?)?;
+    let mut args = env::args().skip(1).peekable();
+
+    // `history <days> [period,delay] [csv|chart]`
+    if args.next_if(|arg| arg == "history").is_some() {
+        let days = usize::from_str(&args.next().context("Expected days")?)?;
+        let lifecycle = args.next().map(|s| Lifecycle::from_str(&s)).transpose()?;
+        let history = puzzle.history(&lifecycle.unwrap_or_default(), days)?;
 
-    println!("Part 1: {}", puzzle.part_1());
-    println!("Part 2: {}", puzzle.part_2());
+        match args.next().as_deref() {
+            Some("csv") => history.write_csv(&mut io::stdout().lock())?,
+            Some("chart") | None => print!("{}", history),
+            Some(format) => bail!("Unknown history format: {}", format),
+        }
+
+        return Ok(());
+    }
+
+    // `<days> [period,delay] [modulus]`
+    if let Some(days) = args.next() {
+        let days = u64::from_str(&days)?;
+        let lifecycle = args.next().map(|s| Lifecycle::from_str(&s)).transpose()?;
+        let modulus = args.next().map(|s| u64::from_str(&s)).transpose()?;
+        let population = puzzle.population(&lifecycle.unwrap_or_default(), days, modulus)?;
+
+        println!("Population: {}", population);
+
+        return Ok(());
+    }
+
+    println!("Part 1: {}", puzzle.part_1()?);
+    println!("Part 2: {}", puzzle.part_2()?);
 
     Ok(())
 }
@@ -56,13 +360,83 @@ mod test {
 
     #[test]
     fn part_1() -> Result<(), Error> {
-        assert_eq!(Puzzle::from_str(INPUT)?.part_1(), 5934);
+        assert_eq!(Puzzle::from_str(INPUT)?.part_1()?, 5934);
         Ok(())
     }
 
     #[test]
     fn part_2() -> Result<(), Error> {
-        assert_eq!(Puzzle::from_str(INPUT)?.part_2(), 26984457539);
+        assert_eq!(Puzzle::from_str(INPUT)?.part_2()?, 26984457539);
+        Ok(())
+    }
+
+    #[test]
+    fn lifecycle() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str(INPUT)?;
+        let lifecycle = Lifecycle::from_str("5,3")?;
+
+        // Day by day, with every fish tracked individually
+        let mut fish = vec![3, 4, 3, 1, 2];
+
+        for _ in 0..40 {
+            let born = fish.iter().filter(|t| **t == 0).count();
+
+            for t in fish.iter_mut() {
+                *t = if *t == 0 { 4 } else { *t - 1 };
+            }
+
+            fish.extend(iter::repeat_n(7, born));
+        }
+
+        assert_eq!(puzzle.population(&lifecycle, 40, None)?, fish.len() as u128);
+
+        // Timers beyond the lifecycle are rejected rather than allocated
+        let puzzle = Puzzle::from_str("3,4,3000000000000")?;
+
+        assert!(puzzle.population(&Lifecycle::default(), 18, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn modulus() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str(INPUT)?;
+        let lifecycle = Lifecycle::default();
+        let prime = 1_000_000_007;
+
+        assert_eq!(
+            puzzle.population(&lifecycle, 256, Some(prime))?,
+            26984457539 % u128::from(prime)
+        );
+        assert!(puzzle.population(&lifecycle, 1_000_000, None).is_err());
+        assert!(puzzle
+            .population(&lifecycle, 1_000_000_000_000_000, Some(prime))
+            .is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn history() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str(INPUT)?;
+        let history = puzzle.history(&Lifecycle::default(), 18)?;
+
+        assert_eq!(history.days[0], [0, 1, 1, 2, 1, 0, 0, 0, 0]);
+        assert_eq!(history.days[1], [1, 1, 2, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(history.totals[18], 26);
+        assert_eq!(
+            history.totals[18],
+            puzzle.population(&Lifecycle::default(), 18, None)?
+        );
+
+        let mut csv = Vec::new();
+
+        history.write_csv(&mut csv)?;
+
+        let csv = String::from_utf8(csv)?;
+        let mut lines = csv.lines();
+
+        assert_eq!(lines.next(), Some("day,t0,t1,t2,t3,t4,t5,t6,t7,t8,total"));
+        assert_eq!(lines.next(), Some("0,0,1,1,2,1,0,0,0,0,5"));
+        assert_eq!(lines.count(), 18);
         Ok(())
     }
 }
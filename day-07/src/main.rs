@@ -1,34 +1,364 @@
-use std::convert;
+use std::env;
 
 use util::*;
 
 #[derive(Debug)]
 struct Puzzle {
-    crabs: Vec<i64>,
+    crabs: Vec<Crab>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Crab {
+    position: i64,
+    // Fuel multiplier
+    weight: i64,
+}
+
+impl FromStr for Crab {
+    type Err = Error;
+
+    // `position` or `position*weight`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (position, weight) = match s.split_once('*') {
+            Some((position, weight)) => (position, i64::from_str(weight)?),
+            None => (s, 1),
+        };
+
+        if weight < 1 {
+            bail!("Crab weight must be positive: {}", s);
+        }
+
+        Ok(Self {
+            position: i64::from_str(position)?,
+            weight,
+        })
+    }
 }
 
 impl Puzzle {
-    fn min_max(&self) -> Result<impl Iterator<Item = i64>, Error> {
+    fn min_max(&self) -> Result<RangeInclusive<i64>, Error> {
         self.crabs
             .iter()
-            .min()
-            .and_then(|&min| self.crabs.iter().max().map(|&max| min..=max))
+            .map(|crab| crab.position)
+            .minmax()
+            .into_option()
+            .map(|(min, max)| min..=max)
             .context("No crabs")
     }
 
-    fn fuel(&self, rate: impl Fn(i64) -> i64) -> Result<i64, Error> {
-        self.min_max()?
-            .map(|n| self.crabs.iter().map(|m| rate((n - m).abs())).sum::<i64>())
+    // Saturates, only far beyond any fuel that fits in an `i64`
+    fn cost(&self, position: i64, rate: impl Fn(i64) -> i128) -> i128 {
+        self.crabs
+            .iter()
+            .map(|crab| {
+                i128::from(crab.weight).saturating_mul(rate((position - crab.position).abs()))
+            })
+            .fold(0, i128::saturating_add)
+    }
+
+    fn fuel(cost: i128) -> Result<i64, Error> {
+        i64::try_from(cost).context("Fuel overflows")
+    }
+
+    // Tries every position, kept for verifying the faster solutions
+    fn brute_force(&self, rate: impl Fn(i64) -> i128) -> Result<i64, Error> {
+        let cost = self
+            .min_max()?
+            .map(|n| self.cost(n, &rate))
             .min()
+            .context("No crabs")?;
+
+        Self::fuel(cost)
+    }
+
+    // Total cost is convex for any convex, non-decreasing rate, so binary
+    // search for the first position where moving right stops helping
+    fn convex(&self, rate: impl Fn(i64) -> i128) -> Result<i64, Error> {
+        let range = self.min_max()?;
+        let (mut lo, mut hi) = (*range.start(), *range.end());
+
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let (here, next) = (self.cost(mid, &rate), self.cost(mid + 1, &rate));
+
+            // Two saturated costs say nothing about the direction
+            if here == i128::MAX && next == i128::MAX {
+                bail!("Fuel overflows around position {}", mid);
+            }
+
+            if next >= here {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+
+        Self::fuel(self.cost(lo, rate))
+    }
+
+    fn total_weight(&self) -> Result<i64, Error> {
+        self.crabs
+            .iter()
+            .try_fold(0, |total: i64, crab| total.checked_add(crab.weight))
+            .context("Crab weights overflow")
+    }
+
+    // Any weighted median minimises the sum of weighted distances
+    fn median(&self) -> Result<i64, Error> {
+        let total = self.total_weight()?;
+        let mut crabs = self.crabs.clone();
+        let mut seen = 0;
+
+        crabs.sort_unstable_by_key(|crab| crab.position);
+
+        crabs
+            .into_iter()
+            .find(|crab| {
+                seen += crab.weight;
+                seen >= total - seen
+            })
+            .map(|crab| crab.position)
             .context("No crabs")
     }
 
+    // Clusters of crabs sharing a target are contiguous once sorted, so
+    // dynamic programming over split points finds the optimal K targets
+    fn targets(&self, k: usize) -> Result<Targets, Error> {
+        let clusters = Clusters::new(&self.crabs)?;
+        let n = clusters.positions.len();
+        let k = k.min(n);
+
+        if k == 0 {
+            bail!("Expected crabs and at least one target");
+        }
+
+        // `best[j][i]`: cheapest cost of the first `i` positions with `j + 1` targets
+        let mut best = vec![vec![i64::MAX; n + 1]; k];
+        let mut split = vec![vec![0; n + 1]; k];
+
+        for (i, cost) in best[0].iter_mut().enumerate().skip(1) {
+            *cost = clusters.cost(0, i);
+        }
+
+        for j in 1..k {
+            for i in (j + 1)..=n {
+                for s in j..i {
+                    let cost = best[j - 1][s].saturating_add(clusters.cost(s, i));
+
+                    if cost < best[j][i] {
+                        best[j][i] = cost;
+                        split[j][i] = s;
+                    }
+                }
+            }
+        }
+
+        let mut positions = Vec::with_capacity(k);
+        let mut end = n;
+
+        for j in (0..k).rev() {
+            let start = if j == 0 { 0 } else { split[j][end] };
+
+            positions.push(clusters.median(start, end));
+            end = start;
+        }
+
+        positions.reverse();
+
+        // Ties between two targets go to the left one
+        let assignments = self
+            .crabs
+            .iter()
+            .map(|crab| {
+                let i = positions.partition_point(|p| *p < crab.position);
+
+                match (i.checked_sub(1), positions.get(i)) {
+                    (Some(l), Some(r)) if crab.position - positions[l] <= r - crab.position => l,
+                    (Some(l), None) => l,
+                    _ => i,
+                }
+            })
+            .collect();
+
+        Ok(Targets {
+            crabs: self.crabs.clone(),
+            positions,
+            assignments,
+            fuel: best[k - 1][n],
+        })
+    }
+
     fn part_1(&self) -> Result<i64, Error> {
-        self.fuel(convert::identity)
+        Self::fuel(self.cost(self.median()?, Rate::Linear.cost()))
     }
 
+    // The triangular optimum lies within half a step of the weighted mean
     fn part_2(&self) -> Result<i64, Error> {
-        self.fuel(|n| (n * (n + 1)) / 2)
+        let weight = self.total_weight()?;
+        let sum = self
+            .crabs
+            .iter()
+            .try_fold(0, |sum: i64, crab| {
+                sum.checked_add(crab.position.checked_mul(crab.weight)?)
+            })
+            .context("Weighted positions overflow")?;
+
+        let mean = sum.checked_div(weight).context("No crabs")?;
+        let rate = Rate::Triangular.cost();
+
+        let cost = ((mean - 1)..=(mean + 1))
+            .map(|n| self.cost(n, &rate))
+            .min()
+            .context("No crabs")?;
+
+        Self::fuel(cost)
+    }
+}
+
+// Crabs grouped by position, with prefix sums for constant time range costs
+struct Clusters {
+    positions: Vec<i64>,
+    // Prefix sums of weight and weight * position
+    weights: Vec<i64>,
+    moments: Vec<i64>,
+}
+
+impl Clusters {
+    fn new(crabs: &[Crab]) -> Result<Self, Error> {
+        let mut grouped = Vec::<(i64, i64)>::new();
+
+        for crab in crabs.iter().sorted_by_key(|crab| crab.position) {
+            match grouped.last_mut() {
+                Some((position, weight)) if *position == crab.position => {
+                    *weight = weight
+                        .checked_add(crab.weight)
+                        .context("Crab weights overflow")?;
+                }
+                _ => grouped.push((crab.position, crab.weight)),
+            }
+        }
+
+        let mut weights = vec![0_i64];
+        let mut moments = vec![0];
+
+        for (position, weight) in &grouped {
+            let moment = position
+                .checked_mul(*weight)
+                .and_then(|moment| moment.checked_add(moments[moments.len() - 1]));
+
+            weights.push(
+                weights[weights.len() - 1]
+                    .checked_add(*weight)
+                    .context("Crab weights overflow")?,
+            );
+            moments.push(moment.context("Weighted positions overflow")?);
+        }
+
+        Ok(Self {
+            positions: grouped.into_iter().map(|(position, _)| position).collect(),
+            weights,
+            moments,
+        })
+    }
+
+    // Index of the weighted median of positions `start..end`
+    fn median_index(&self, start: usize, end: usize) -> usize {
+        let total = self.weights[end] - self.weights[start];
+        let half = self.weights[start..=end]
+            .partition_point(|w| w - self.weights[start] < total - (w - self.weights[start]));
+
+        start + half - 1
+    }
+
+    fn median(&self, start: usize, end: usize) -> i64 {
+        self.positions[self.median_index(start, end)]
+    }
+
+    // Fuel to move positions `start..end` to their weighted median
+    fn cost(&self, start: usize, end: usize) -> i64 {
+        let m = self.median_index(start, end);
+        let p = self.positions[m];
+        let (w, s) = (&self.weights, &self.moments);
+        let range = |a: usize, b: usize| {
+            let sub = |v: &[i64]| i128::from(v[b]) - i128::from(v[a]);
+
+            (sub(w), sub(s))
+        };
+        let (p, (lw, ls), (rw, rs)) = (i128::from(p), range(start, m), range(m + 1, end));
+
+        // Saturates like the costs summed over targets
+        i64::try_from(p * lw - ls + rs - p * rw).unwrap_or(i64::MAX)
+    }
+}
+
+#[derive(Debug)]
+struct Targets {
+    crabs: Vec<Crab>,
+    positions: Vec<i64>,
+    // Index into `positions` for each crab
+    assignments: Vec<usize>,
+    fuel: i64,
+}
+
+impl fmt::Display for Targets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Targets: {}", self.positions.iter().join(", "))?;
+        writeln!(f, "Fuel: {}", self.fuel)?;
+        writeln!(f)?;
+        writeln!(f, "  crab  position  weight  target   fuel")?;
+
+        for (i, (crab, target)) in self.crabs.iter().zip(&self.assignments).enumerate() {
+            let target = self.positions[*target];
+
+            writeln!(
+                f,
+                "  {:>4}  {:>8}  {:>6}  {:>6}  {:>5}",
+                i + 1,
+                crab.position,
+                crab.weight,
+                target,
+                (i128::from(target) - i128::from(crab.position)).abs() * i128::from(crab.weight),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Rate {
+    Linear,
+    Triangular,
+    Power(u32),
+}
+
+impl Rate {
+    fn cost(self) -> impl Fn(i64) -> i128 {
+        move |n: i64| {
+            let n = i128::from(n);
+
+            match self {
+                Rate::Linear => n,
+                Rate::Triangular => (n * (n + 1)) / 2,
+                Rate::Power(exp) => n.saturating_pow(exp),
+            }
+        }
+    }
+}
+
+impl FromStr for Rate {
+    type Err = Error;
+
+    // `linear`, `triangular` or `power:<exp>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rate = match s.split_once(':') {
+            None if s == "linear" => Rate::Linear,
+            None if s == "triangular" => Rate::Triangular,
+            Some(("power", exp)) => Rate::Power(u32::from_str(exp)?),
+            _ => bail!("Unknown rate: {}", s),
+        };
+
+        Ok(rate)
     }
 }
 
@@ -39,7 +369,7 @@ impl FromStr for Puzzle {
         let crabs = s
             .trim()
             .split(',')
-            .map(i64::from_str)
+            .map(|crab| Crab::from_str(crab.trim()))
             .collect::<Result<_, _>>()?;
 
         Ok(Self { crabs })
@@ -48,6 +378,41 @@ impl FromStr for Puzzle {
 
 fn main() -> Result<(), Error> {
     let puzzle = Puzzle::from_str(&read_stdin()?)?;
+    let mut args = env::args().skip(1).peekable();
+
+    // `verify` compares both parts against trying every position
+    if args.next_if(|arg| arg == "verify").is_some() {
+        for (part, rate, fuel) in [
+            (1, Rate::Linear, puzzle.part_1()?),
+            (2, Rate::Triangular, puzzle.part_2()?),
+        ] {
+            let expected = puzzle.brute_force(rate.cost())?;
+
+            println!("Part {}: {} (brute force {})", part, fuel, expected);
+
+            if fuel != expected {
+                bail!("Part {} does not match brute force", part);
+            }
+        }
+
+        return Ok(());
+    }
+
+    // `targets <k>` aligns crabs to their nearest of `k` positions
+    if args.next_if(|arg| arg == "targets").is_some() {
+        let k = usize::from_str(&args.next().context("Expected target count")?)?;
+
+        print!("{}", puzzle.targets(k)?);
+
+        return Ok(());
+    }
+
+    // Any convex rate, e.g. `power:3`
+    if let Some(rate) = args.next() {
+        println!("Fuel: {}", puzzle.convex(Rate::from_str(&rate)?.cost())?);
+
+        return Ok(());
+    }
 
     println!("Part 1: {}", puzzle.part_1()?);
     println!("Part 2: {}", puzzle.part_2()?);
@@ -72,4 +437,73 @@ mod test {
         assert_eq!(Puzzle::from_str(INPUT)?.part_2()?, 168);
         Ok(())
     }
+
+    #[test]
+    fn convex() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str(INPUT)?;
+
+        for rate in ["linear", "triangular", "power:2", "power:3"] {
+            let rate = Rate::from_str(rate)?;
+
+            assert_eq!(
+                puzzle.convex(rate.cost())?,
+                puzzle.brute_force(rate.cost())?
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn large_range() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str("0,1000000000,3,999999999,500000000")?;
+
+        assert_eq!(puzzle.part_1()?, 1999999996);
+        assert_eq!(puzzle.part_2()?, puzzle.convex(Rate::Triangular.cost())?);
+
+        // Costs away from the optimum overflow an `i64`
+        let puzzle = Puzzle::from_str("0,2000000*100")?;
+        let cube = Rate::Power(3).cost();
+
+        assert_eq!(puzzle.convex(&cube)?, 6611570247935867768);
+        assert_eq!(puzzle.convex(&cube)?, puzzle.brute_force(&cube)?);
+        assert!(puzzle.convex(Rate::Power(40).cost()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn weighted() -> Result<(), Error> {
+        // Overflowing weighted sums are errors rather than panics
+        let puzzle = Puzzle::from_str("5*4000000000000000000,6")?;
+
+        assert!(puzzle.part_2().is_err());
+        assert!(puzzle.targets(1).is_err());
+        assert!(
+            Puzzle::from_str("1*5000000000000000000,2*5000000000000000000")?
+                .part_1()
+                .is_err()
+        );
+
+        let puzzle = Puzzle::from_str("16*3,1,2*2,0,4,7,1,14")?;
+        let expanded = Puzzle::from_str("16,16,16,1,2,2,0,4,7,1,14")?;
+
+        assert_eq!(puzzle.part_1()?, expanded.part_1()?);
+        assert_eq!(puzzle.part_2()?, expanded.part_2()?);
+        assert_eq!(puzzle.part_1()?, puzzle.brute_force(Rate::Linear.cost())?);
+        assert!(Puzzle::from_str("1*0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn targets() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str(INPUT)?;
+        let targets = puzzle.targets(2)?;
+
+        assert_eq!(targets.positions, [2, 14]);
+        assert_eq!(targets.fuel, 13);
+        assert_eq!(targets.assignments, [1, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(puzzle.targets(1)?.fuel, 37);
+        assert_eq!(puzzle.targets(20)?.fuel, 0);
+        Ok(())
+    }
 }
@@ -1,15 +1,20 @@
+use std::{env, fs};
+
 use util::*;
 
-const ZERO: &[u8] = b"abcefg";
-const ONE: &[u8] = b"cf";
-const TWO: &[u8] = b"acdeg";
-const THREE: &[u8] = b"acdfg";
-const FOUR: &[u8] = b"bcdf";
-const FIVE: &[u8] = b"abdfg";
-const SIX: &[u8] = b"abdefg";
-const SEVEN: &[u8] = b"acf";
-const EIGHT: &[u8] = b"abcdefg";
-const NINE: &[u8] = b"abcdfg";
+// One glyph per line, followed by the segments it lights
+const SEVEN_SEGMENT: &str = r#"
+    0 abcefg
+    1 cf
+    2 acdeg
+    3 acdfg
+    4 bcdf
+    5 abdfg
+    6 abdefg
+    7 acf
+    8 abcdefg
+    9 abcdfg
+"#;
 
 #[derive(Debug)]
 struct Pattern {
@@ -17,52 +22,448 @@ struct Pattern {
     output: Vec<String>,
 }
 
-fn wire_lookup(pattern: &[u8], num: &[u8]) -> Result<usize, Error> {
-    const OFFSET: u8 = b'a';
+#[derive(Debug)]
+enum WireError {
+    // More than one mapping explains every sample
+    Ambiguous,
+    // No mapping explains every sample
+    Contradictory,
+}
 
-    let mut num = num
-        .iter()
-        .map(|c| pattern[usize::from(c - OFFSET)] + OFFSET)
-        .collect::<Vec<_>>();
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
 
-    num.sort_unstable();
+impl error::Error for WireError {}
 
-    [ZERO, ONE, TWO, THREE, FOUR, FIVE, SIX, SEVEN, EIGHT, NINE]
-        .into_iter()
-        .enumerate()
-        .find_map(|(i, n)| (num == n).then(move || i))
-        .context("Invalid order")
+fn ones(mut mask: u32) -> impl Iterator<Item = usize> {
+    iter::from_fn(move || {
+        let i = mask.trailing_zeros() as usize;
+
+        mask &= mask.checked_sub(1)?;
+
+        Some(i)
+    })
+}
+
+// Segments lit by a word through the wire mapping
+fn translate(pattern: &[u8], word: u32) -> u32 {
+    ones(word).fold(0, |mask, wire| mask | 1 << pattern[wire])
+}
+
+// Segments `a`, `b`, ... and the glyphs they draw
+#[derive(Debug)]
+struct Definition {
+    wires: usize,
+    glyphs: Vec<(char, u32)>,
+    // Number of glyphs of each length containing each segment
+    signatures: Vec<Vec<usize>>,
+}
+
+impl Definition {
+    const MAX_WIRES: usize = 26;
+
+    fn seven_segment() -> Self {
+        Self::from_str(SEVEN_SEGMENT).expect("Valid seven segment definition")
+    }
+
+    fn all(&self) -> u32 {
+        (1 << self.wires) - 1
+    }
+
+    // Bit `i` set for wire or segment `a + i`
+    fn mask(&self, word: &str) -> Result<u32, Error> {
+        word.bytes().try_fold(0, |mask, c| {
+            let i = usize::from(c.wrapping_sub(b'a'));
+
+            if i >= self.wires {
+                bail!("Invalid wire: {}", char::from(c));
+            }
+
+            Ok(mask | 1 << i)
+        })
+    }
+
+    fn glyph(&self, segments: u32) -> Option<char> {
+        self.glyphs
+            .iter()
+            .find(|(_, g)| *g == segments)
+            .map(|(c, _)| *c)
+    }
+
+    fn signatures(wires: usize, words: &[u32]) -> Vec<Vec<usize>> {
+        (0..wires)
+            .map(|i| {
+                let mut lengths = vec![0; wires + 1];
+
+                for word in words.iter().filter(|w| *w & (1 << i) != 0) {
+                    lengths[word.count_ones() as usize] += 1;
+                }
+
+                lengths
+            })
+            .collect()
+    }
+
+    // Narrows down the segments each wire can drive from the sample lengths,
+    // then searches whatever choices remain
+    fn solve(&self, samples: &[u32], limit: usize) -> Vec<Vec<u8>> {
+        let all = self.all();
+        let mut candidates = vec![all; self.wires];
+
+        for sample in samples {
+            let len = sample.count_ones();
+            let (union, intersection) = self
+                .glyphs
+                .iter()
+                .map(|(_, g)| *g)
+                .filter(|g| g.count_ones() == len)
+                .fold((0, all), |(u, i), g| (u | g, i & g));
+
+            // Lit wires drive a segment of some glyph of this length and
+            // unlit wires cannot drive a segment shared by all of them
+            for (wire, candidate) in candidates.iter_mut().enumerate() {
+                if sample & (1 << wire) != 0 {
+                    *candidate &= union;
+                } else {
+                    *candidate &= !intersection;
+                }
+            }
+        }
+
+        // With each glyph sampled once, a wire appears in as many words of
+        // each length as the segment it drives; a repeated sample means
+        // another glyph is missing
+        if samples.len() == self.glyphs.len() && samples.iter().all_unique() {
+            let wires = Self::signatures(self.wires, samples);
+
+            for (candidate, signature) in candidates.iter_mut().zip(&wires) {
+                *candidate &= ones(all)
+                    .filter(|s| self.signatures[*s] == *signature)
+                    .fold(0, |m, s| m | 1 << s);
+            }
+        }
+
+        let mut search = Search {
+            definition: self,
+            candidates: &candidates,
+            samples,
+            limit,
+            pattern: Vec::new(),
+            solutions: Vec::new(),
+        };
+
+        search.run(0);
+        search.solutions
+    }
+
+    // Words with a length shared by no other glyph
+    fn is_unique_length(&self, word: &str) -> bool {
+        self.glyphs
+            .iter()
+            .filter(|(_, g)| g.count_ones() as usize == word.len())
+            .count()
+            == 1
+    }
+}
+
+impl FromStr for Definition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let glyphs =
+            s.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| -> Result<_, Error> {
+                    let (glyph, segments) = line.split_once(' ').context("Missing segments")?;
+                    let glyph = glyph.chars().exactly_one().map_err(|_| {
+                        Error::msg(format!("Glyph must be one character: {}", glyph))
+                    })?;
+
+                    Ok((glyph, segments.trim()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+        let wires = glyphs
+            .iter()
+            .flat_map(|(_, segments)| segments.bytes())
+            .map(|c| usize::from(c.wrapping_sub(b'a')) + 1)
+            .max()
+            .context("Expected glyphs")?;
+
+        if wires > Self::MAX_WIRES {
+            bail!("Segments must be named a to z");
+        }
+
+        let mut definition = Self {
+            wires,
+            glyphs: Vec::new(),
+            signatures: Vec::new(),
+        };
+
+        for (glyph, segments) in glyphs {
+            let mask = definition.mask(segments)?;
+
+            if definition.glyph(mask).is_some() {
+                bail!("Glyph {} duplicates segments {}", glyph, segments);
+            }
+
+            definition.glyphs.push((glyph, mask));
+        }
+
+        let masks = definition
+            .glyphs
+            .iter()
+            .map(|(_, g)| *g)
+            .collect::<Vec<_>>();
+
+        definition.signatures = Self::signatures(wires, &masks);
+
+        Ok(definition)
+    }
 }
 
 impl Pattern {
-    fn pattern(&self) -> Result<Vec<u8>, Error> {
-        (0..7u8)
-            .permutations(7)
-            .find(|pattern| {
-                self.samples
+    fn samples(&self, definition: &Definition) -> Result<Vec<u32>, Error> {
+        self.samples.iter().map(|s| definition.mask(s)).collect()
+    }
+
+    fn pattern(&self, definition: &Definition) -> Result<Vec<u8>, Error> {
+        let samples = self.samples(definition)?;
+        let mut solutions = definition.solve(&samples, 2);
+
+        match solutions.len() {
+            0 => Err(WireError::Contradictory.into()),
+            1 => Ok(solutions.swap_remove(0)),
+            _ => Err(WireError::Ambiguous.into()),
+        }
+    }
+
+    // Drops as few samples as possible until some mapping explains the rest,
+    // then compares every word that mapping cannot decode to its nearest glyph
+    fn diagnose(&self, definition: &Definition) -> Result<Diagnosis, Error> {
+        const MAX_MAPPINGS: usize = 8;
+
+        let samples = self.samples(definition)?;
+        let mut mappings = Vec::new();
+        let mut dropped = 0;
+
+        while mappings.is_empty() && dropped <= samples.len() {
+            for skip in (0..samples.len()).combinations(dropped) {
+                let kept = samples
                     .iter()
-                    .all(|sample| wire_lookup(pattern, sample.as_bytes()).is_ok())
-            })
-            .context("Invalid wire combination")
+                    .enumerate()
+                    .filter(|(i, _)| !skip.contains(i))
+                    .map(|(_, s)| *s)
+                    .collect::<Vec<_>>();
+
+                for mapping in definition.solve(&kept, MAX_MAPPINGS) {
+                    if mappings.len() < MAX_MAPPINGS && !mappings.contains(&mapping) {
+                        mappings.push(mapping);
+                    }
+                }
+            }
+
+            dropped += 1;
+        }
+
+        let mapping = mappings.first().context("No mapping explains any sample")?;
+        let words = self
+            .samples
+            .iter()
+            .map(|word| (false, word))
+            .chain(self.output.iter().map(|word| (true, word)));
+
+        let mut faults = Vec::new();
+
+        for (output, word) in words {
+            let lit = translate(mapping, definition.mask(word)?);
+
+            if definition.glyph(lit).is_some() {
+                continue;
+            }
+
+            let (nearest, segments) = definition
+                .glyphs
+                .iter()
+                .min_by_key(|(_, g)| (g ^ lit).count_ones())
+                .context("Expected glyphs")?;
+
+            faults.push(Fault {
+                word: word.clone(),
+                output,
+                lit,
+                nearest: *nearest,
+                missing: segments & !lit,
+                extra: lit & !segments,
+            });
+        }
+
+        Ok(Diagnosis {
+            explained: samples.len() + 1 - dropped,
+            samples: samples.len(),
+            mappings,
+            faults,
+        })
     }
 
-    fn nums(&self) -> Result<Vec<usize>, Error> {
-        let pattern = self.pattern()?;
+    fn decode(&self, definition: &Definition) -> Result<String, Error> {
+        let pattern = self.pattern(definition)?;
 
         self.output
             .iter()
-            .map(|n| wire_lookup(&pattern, n.as_bytes()))
+            .map(|word| {
+                definition
+                    .glyph(translate(&pattern, definition.mask(word)?))
+                    .context("Invalid order")
+            })
             .collect()
     }
 
-    fn output(&self) -> Result<usize, Error> {
-        let (_, output) = self
-            .nums()?
-            .into_iter()
-            .rev()
-            .fold((1, 0), |(o, sum), n| (o * 10, sum + (o * n)));
+    fn output(&self, definition: &Definition) -> Result<usize, Error> {
+        Ok(usize::from_str(&self.decode(definition)?)?)
+    }
+}
+
+fn segment_names(mask: u32) -> String {
+    ones(mask).map(|i| char::from(b'a' + i as u8)).collect()
+}
+
+// A word the best mapping cannot decode, compared with its nearest glyph
+#[derive(Debug)]
+struct Fault {
+    word: String,
+    output: bool,
+    // Segments lit through the mapping
+    lit: u32,
+    nearest: char,
+    missing: u32,
+    extra: u32,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (missing, extra) = (segment_names(self.missing), segment_names(self.extra));
+
+        write!(
+            f,
+            "{} {} lights {}, nearest {}: ",
+            if self.output { "output" } else { "sample" },
+            self.word,
+            segment_names(self.lit),
+            self.nearest,
+        )?;
+
+        match (missing.len(), extra.len()) {
+            (1, 0) => write!(f, "segment {} is dark, likely broken", missing),
+            (0, 1) => write!(f, "segment {} is lit, likely shorted", extra),
+            (1, 1) => write!(f, "segments {} and {} look swapped", missing, extra),
+            _ => write!(f, "missing {:?}, extra {:?}", missing, extra),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Diagnosis {
+    // Samples explained by the best mappings
+    explained: usize,
+    samples: usize,
+    mappings: Vec<Vec<u8>>,
+    // Against the first mapping
+    faults: Vec<Fault>,
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Explains {} of {} samples with {} mapping(s)",
+            self.explained,
+            self.samples,
+            self.mappings.len()
+        )?;
+
+        for mapping in &self.mappings {
+            let wires = mapping
+                .iter()
+                .enumerate()
+                .map(|(wire, segment)| {
+                    format!(
+                        "{}->{}",
+                        segment_names(1 << wire),
+                        segment_names(1 << segment)
+                    )
+                })
+                .join(" ");
+
+            writeln!(f, "  mapping {}", wires)?;
+        }
+
+        for fault in &self.faults {
+            writeln!(f, "  {}", fault)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Assigns the remaining candidates wire by wire, stopping once `limit`
+// solutions are found; two are enough to know the samples are ambiguous
+struct Search<'a> {
+    definition: &'a Definition,
+    candidates: &'a [u32],
+    samples: &'a [u32],
+    limit: usize,
+    pattern: Vec<u8>,
+    solutions: Vec<Vec<u8>>,
+}
+
+impl Search<'_> {
+    // Every sample must still fit some glyph of its length given the
+    // segments of the wires assigned so far
+    fn is_consistent(&self) -> bool {
+        let assigned = (1 << self.pattern.len()) - 1;
+
+        self.samples.iter().all(|sample| {
+            let lit = translate(&self.pattern, sample & assigned);
+            let unlit = translate(&self.pattern, !sample & assigned);
+
+            self.definition.glyphs.iter().any(|(_, g)| {
+                g.count_ones() == sample.count_ones() && g & lit == lit && g & unlit == 0
+            })
+        })
+    }
+
+    fn run(&mut self, used: u32) {
+        if self.solutions.len() >= self.limit {
+            return;
+        }
+
+        let wire = self.pattern.len();
+
+        if wire == self.candidates.len() {
+            if self.is_consistent() {
+                self.solutions.push(self.pattern.clone());
+            }
+
+            return;
+        }
+
+        let options = self.candidates[wire] & !used;
+
+        // Forced choices are left for the check once fully assigned
+        if options.count_ones() > 1 && !self.is_consistent() {
+            return;
+        }
 
-        Ok(output)
+        for segment in ones(options) {
+            self.pattern.push(segment as u8);
+            self.run(used | 1 << segment);
+            self.pattern.pop();
+        }
     }
 }
 
@@ -70,7 +471,7 @@ impl FromStr for Pattern {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let parse = |s: &str| s.trim().split(' ').map(String::from).collect::<Vec<_>>();
+        let parse = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<_>>();
         let (samples, output) = s.trim().split_once('|').context("Missing output")?;
 
         Ok(Self {
@@ -81,25 +482,55 @@ impl FromStr for Pattern {
 }
 
 fn part_1(patterns: &[Pattern]) -> usize {
+    let definition = Definition::seven_segment();
+
     patterns
         .iter()
         .flat_map(|pattern| pattern.output.iter())
-        .filter(|output| {
-            [ONE, FOUR, SEVEN, EIGHT]
-                .iter()
-                .any(|d| output.len() == d.len())
-        })
+        .filter(|output| definition.is_unique_length(output))
         .count()
 }
 
 fn part_2(patterns: &[Pattern]) -> Result<usize, Error> {
+    let definition = Definition::seven_segment();
+
     patterns
         .iter()
-        .try_fold(0, |acc, pattern| Ok(pattern.output()? + acc))
+        .try_fold(0, |acc, pattern| Ok(pattern.output(&definition)? + acc))
 }
 
 fn main() -> Result<(), Error> {
-    let patterns = read_stdin()?.try_from_lines()?;
+    let patterns = read_stdin()?.try_from_lines::<Pattern>()?;
+
+    let mut args = env::args().skip(1).peekable();
+    let diagnose = args.next_if(|arg| arg == "diagnose").is_some();
+
+    // Optional definition file, otherwise seven segment digits
+    let definition = match args.next() {
+        Some(path) => Some(Definition::from_str(&fs::read_to_string(path)?)?),
+        None => None,
+    };
+
+    if diagnose {
+        let definition = definition.unwrap_or_else(Definition::seven_segment);
+
+        for (i, pattern) in patterns.iter().enumerate() {
+            if let Err(err) = pattern.decode(&definition) {
+                println!("Entry {}: {}", i + 1, err);
+                print!("{}", pattern.diagnose(&definition)?);
+            }
+        }
+
+        return Ok(());
+    }
+
+    if let Some(definition) = definition {
+        for pattern in &patterns {
+            println!("{}", pattern.decode(&definition)?);
+        }
+
+        return Ok(());
+    }
 
     println!("Part 1: {}", part_1(&patterns));
     println!("Part 2: {}", part_2(&patterns)?);
@@ -129,7 +560,41 @@ mod test {
         let pattern = Pattern::from_str(
             "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf",
         )?;
-        assert_eq!(pattern.output()?, 5353);
+        assert_eq!(pattern.output(&Definition::seven_segment())?, 5353);
+        Ok(())
+    }
+
+    #[test]
+    fn deduction() -> Result<(), Error> {
+        let definition = Definition::seven_segment();
+        let error = |s| {
+            Pattern::from_str(s)
+                .unwrap()
+                .pattern(&definition)
+                .unwrap_err()
+        };
+
+        // Only the digit one, so `c` and `f` can be swapped
+        assert!(matches!(
+            error("ab | ab").downcast_ref(),
+            Some(WireError::Ambiguous)
+        ));
+        // No digit lights three wires alongside a two wire one
+        assert!(matches!(
+            error("ab abcd acd | ab").downcast_ref(),
+            Some(WireError::Contradictory)
+        ));
+
+        let pattern =
+            Pattern::from_str("acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | ab")?;
+
+        assert_eq!(pattern.pattern(&definition)?, [2, 5, 6, 0, 1, 3, 4]);
+
+        // As many samples as glyphs, but `1` twice and no `8`
+        let pattern =
+            Pattern::from_str("cf cf acf bcdf acdeg acdfg abdfg abdefg abcefg abcdfg | cf acf")?;
+
+        assert_eq!(pattern.output(&definition)?, 17);
         Ok(())
     }
 
@@ -144,4 +609,63 @@ mod test {
         assert_eq!(super::part_2(&INPUT.try_from_lines()?)?, 61229);
         Ok(())
     }
+
+    #[test]
+    fn diagnose() -> Result<(), Error> {
+        let definition = Definition::seven_segment();
+
+        // `acedgfb` (8) with wire `d`, driving segment `a`, broken
+        let pattern = Pattern::from_str(
+            "acegfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf",
+        )?;
+        let diagnosis = pattern.diagnose(&definition)?;
+
+        assert!(pattern.pattern(&definition).is_err());
+        assert_eq!(diagnosis.explained, 9);
+        assert_eq!(diagnosis.mappings, [vec![2, 5, 6, 0, 1, 3, 4]]);
+        assert_eq!(diagnosis.faults.len(), 1);
+        assert_eq!(diagnosis.faults[0].word, "acegfb");
+        assert_eq!(diagnosis.faults[0].nearest, '8');
+        assert_eq!(segment_names(diagnosis.faults[0].missing), "a");
+
+        // Output `eafb` (4) with wires `b` and `g` swapped
+        let pattern = Pattern::from_str(
+            "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | eafg fcadb",
+        )?;
+        let diagnosis = pattern.diagnose(&definition)?;
+
+        assert_eq!(diagnosis.explained, 10);
+        assert_eq!(diagnosis.faults.len(), 1);
+        assert_eq!(
+            diagnosis.faults[0].to_string(),
+            "output eafg lights bcde, nearest 4: segments f and e look swapped"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn definition() -> Result<(), Error> {
+        // A toy display drawing letters with six segments
+        let definition = Definition::from_str(
+            r#"
+            # glyph segments
+            H bcef
+            E adef
+            L de
+            O abcde
+            W bcdef
+            R abe
+            D bcd
+            "#,
+        )?;
+
+        // Segments scrambled by `a -> c, b -> e, c -> a, d -> f, e -> b, f -> d`
+        let pattern =
+            Pattern::from_str("eabd cfbd fb cebaf eabfd ceb eaf | eabd cfbd fb fb cebaf")?;
+
+        assert_eq!(definition.wires, 6);
+        assert_eq!(pattern.decode(&definition)?, "HELLO");
+        assert!(Definition::from_str("A ab\nB ba").is_err());
+        Ok(())
+    }
 }
@@ -1,3 +1,5 @@
+use std::env;
+
 use util::*;
 
 struct Puzzle {
@@ -26,34 +28,242 @@ impl Puzzle {
             })
     }
 
-    fn fill(&self, state: &mut HashSet<(usize, usize)>, x: usize, y: usize) {
-        let m = self
-            .heights
-            .get(y)
-            .and_then(|row| row.get(x).copied())
-            .unwrap_or(9);
+    fn width(&self) -> usize {
+        self.heights.first().map_or(0, Vec::len)
+    }
 
-        if m >= 9 || !state.insert((x, y)) {
-            return;
+    fn neighbours(
+        &self,
+        connectivity: Connectivity,
+        x: usize,
+        y: usize,
+    ) -> impl Iterator<Item = (usize, usize)> + '_ {
+        connectivity.offsets().iter().filter_map(move |(dx, dy)| {
+            let x = x.checked_add_signed(*dx)?;
+            let y = y.checked_add_signed(*dy)?;
+
+            self.heights.get(y)?.get(x).map(|_| (x, y))
+        })
+    }
+
+    // Cells below the wall reachable from `(x, y)`, kept on an explicit
+    // worklist so a single huge basin cannot overflow the stack
+    fn fill(&self, flood: &Flood, x: usize, y: usize) -> Vec<(usize, usize)> {
+        let width = self.width();
+        let mut seen = vec![false; width * self.heights.len()];
+        let mut stack = Vec::new();
+        let mut cells = Vec::new();
+
+        if flood.is_open(self.heights.get(y).and_then(|row| row.get(x))) {
+            seen[y * width + x] = true;
+            stack.push((x, y));
+        }
+
+        while let Some((x, y)) = stack.pop() {
+            cells.push((x, y));
+
+            for (x, y) in self.neighbours(flood.connectivity, x, y) {
+                if flood.is_open(Some(&self.heights[y][x])) && !seen[y * width + x] {
+                    seen[y * width + x] = true;
+                    stack.push((x, y));
+                }
+            }
+        }
+
+        cells
+    }
+
+    fn basin_size(&self, flood: &Flood, x: usize, y: usize) -> usize {
+        self.fill(flood, x, y).len()
+    }
+
+    // Joins every pair of neighbouring cells below the wall in one sweep,
+    // then labels each set in reading order
+    fn basins(&self, flood: &Flood) -> Basins {
+        let width = self.width();
+        let mut set = DisjointSet::new(width * self.heights.len());
+
+        for (y, row) in self.heights.iter().enumerate() {
+            for (x, n) in row.iter().enumerate() {
+                if !flood.is_open(Some(n)) {
+                    continue;
+                }
+
+                // Each pair once, from the later cell in reading order
+                for (nx, ny) in self.neighbours(flood.connectivity, x, y) {
+                    if (ny, nx) < (y, x) && flood.is_open(Some(&self.heights[ny][nx])) {
+                        set.union(y * width + x, ny * width + nx);
+                    }
+                }
+            }
         }
 
-        self.fill(state, x + 1, y);
-        if x > 0 {
-            self.fill(state, x - 1, y);
+        let mut ids = vec![None::<usize>; width * self.heights.len()];
+        let mut labels = vec![vec![None; width]; self.heights.len()];
+        let mut basins = Vec::<Basin>::new();
+
+        for (y, row) in self.heights.iter().enumerate() {
+            for (x, n) in row.iter().enumerate() {
+                if !flood.is_open(Some(n)) {
+                    continue;
+                }
+
+                let root = set.find(y * width + x);
+                let id = match ids[root] {
+                    Some(id) => {
+                        basins[id].add(*n, (x, y));
+                        id
+                    }
+                    None => {
+                        ids[root] = Some(basins.len());
+                        basins.push(Basin::new(*n, (x, y)));
+                        basins.len() - 1
+                    }
+                };
+
+                labels[y][x] = Some(id);
+            }
         }
 
-        self.fill(state, x, y + 1);
-        if y > 0 {
-            self.fill(state, x, y - 1);
+        Basins { labels, basins }
+    }
+
+    // Lowest strictly lower neighbour, the first in neighbour order on ties
+    fn descent(&self, connectivity: Connectivity, x: usize, y: usize) -> Option<(usize, usize)> {
+        let n = self.heights[y][x];
+
+        self.neighbours(connectivity, x, y)
+            .filter(|(x, y)| self.heights[*y][*x] < n)
+            .min_by_key(|(x, y)| self.heights[*y][*x])
+    }
+
+    // Routes cells with no lower neighbour across their plateau, towards the
+    // nearest cell of the same height that does have one. A plateau with no
+    // way down drains to its first cell in reading order, its only sink
+    fn drain_plateaus(&self, connectivity: Connectivity, flow: &mut [Option<usize>]) {
+        let width = self.width();
+        let mut seen = vec![false; flow.len()];
+        let mut reached = vec![false; flow.len()];
+
+        for start in 0..flow.len() {
+            if flow[start].is_some() || seen[start] {
+                continue;
+            }
+
+            let n = self.heights[start / width][start % width];
+            let mut plateau = vec![start];
+            let mut stack = vec![start];
+
+            seen[start] = true;
+
+            while let Some(i) = stack.pop() {
+                for (x, y) in self.neighbours(connectivity, i % width, i / width) {
+                    let next = y * width + x;
+
+                    if self.heights[y][x] == n && !seen[next] {
+                        seen[next] = true;
+                        plateau.push(next);
+                        stack.push(next);
+                    }
+                }
+            }
+
+            plateau.sort_unstable();
+
+            // Breadth first from the ways down, each cell pointing back
+            // towards the one it was reached from
+            let mut queue = plateau
+                .iter()
+                .copied()
+                .filter(|i| flow[*i].is_some())
+                .collect::<Vec<_>>();
+
+            if queue.is_empty() {
+                queue.push(plateau[0]);
+            }
+
+            for i in &queue {
+                reached[*i] = true;
+            }
+
+            let mut head = 0;
+
+            while let Some(&i) = queue.get(head) {
+                head += 1;
+
+                for (x, y) in self.neighbours(connectivity, i % width, i / width) {
+                    let next = y * width + x;
+
+                    if self.heights[y][x] == n && !reached[next] {
+                        flow[next] = Some(i);
+                        reached[next] = true;
+                        queue.push(next);
+                    }
+                }
+            }
         }
     }
 
-    fn basin_size(&self, x: usize, y: usize) -> usize {
-        let mut state = HashSet::new();
+    // Follows steepest descent from every cell, walls included, sharing the
+    // sink with every cell on the path so each cell is only walked once
+    fn terrain(&self, flood: &Flood) -> Terrain {
+        let width = self.width();
+        let len = width * self.heights.len();
+        let mut flow = (0..len)
+            .map(|i| {
+                self.descent(flood.connectivity, i % width, i / width)
+                    .map(|(x, y)| y * width + x)
+            })
+            .collect::<Vec<_>>();
+
+        self.drain_plateaus(flood.connectivity, &mut flow);
+
+        let mut sinks = vec![None; len];
+        let mut path = Vec::new();
+
+        for start in 0..len {
+            let mut i = start;
+
+            let sink = loop {
+                if let Some(sink) = sinks[i] {
+                    break sink;
+                }
+
+                path.push(i);
+
+                match flow[i] {
+                    Some(next) => i = next,
+                    None => break i,
+                }
+            };
+
+            for i in path.drain(..) {
+                sinks[i] = Some(sink);
+            }
+        }
 
-        self.fill(&mut state, x, y);
+        let basins = self.basins(flood);
+        let ridges = (0..len)
+            .map(|i| (i % width, i / width))
+            .filter(|&(x, y)| {
+                basins.labels[y][x].is_none()
+                    && self
+                        .neighbours(flood.connectivity, x, y)
+                        .filter_map(|(x, y)| basins.labels[y][x])
+                        .unique()
+                        .nth(1)
+                        .is_some()
+            })
+            .collect();
 
-        state.len()
+        Terrain {
+            width,
+            heights: self.heights.concat(),
+            flow,
+            sinks: sinks.into_iter().flatten().collect(),
+            ridges,
+            basins,
+        }
     }
 
     fn part_1(&self) -> u32 {
@@ -61,9 +271,12 @@ impl Puzzle {
     }
 
     fn part_2(&self) -> Result<usize, Error> {
+        // Labelled in one sweep rather than filled from every low point
         let mut sizes = self
-            .low_points()
-            .map(|(_, (x, y))| self.basin_size(x, y))
+            .basins(&Flood::default())
+            .basins
+            .iter()
+            .map(|basin| basin.size)
             .collect::<Vec<_>>();
 
         sizes.sort_unstable();
@@ -90,14 +303,406 @@ impl FromStr for Puzzle {
                     .map(|c| c.to_digit(10).context("Invalid digit"))
                     .collect()
             })
-            .collect::<Result<_, _>>()?;
+            .collect::<Result<Vec<Vec<_>>, _>>()?;
+
+        if heights.iter().map(Vec::len).dedup().count() > 1 {
+            bail!("Rows must have equal width");
+        }
 
         Ok(Self { heights })
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Connectivity {
+    Four,
+    Eight,
+}
+
+impl Connectivity {
+    fn offsets(self) -> &'static [(isize, isize)] {
+        match self {
+            Self::Four => &[(0, -1), (-1, 0), (1, 0), (0, 1)],
+            Self::Eight => &[
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (-1, 0),
+                (1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1),
+            ],
+        }
+    }
+}
+
+impl FromStr for Connectivity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "4" => Ok(Self::Four),
+            "8" => Ok(Self::Eight),
+            _ => bail!("Unknown connectivity: {}", s),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Flood {
+    connectivity: Connectivity,
+    // Cells at or above this height bound the basins
+    wall: u32,
+}
+
+impl Flood {
+    fn is_open(&self, height: Option<&u32>) -> bool {
+        height.is_some_and(|n| *n < self.wall)
+    }
+}
+
+impl Default for Flood {
+    fn default() -> Self {
+        Self {
+            connectivity: Connectivity::Four,
+            wall: 9,
+        }
+    }
+}
+
+// Disjoint set forest over cell indices, union by size with path halving
+struct DisjointSet {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl DisjointSet {
+    fn new(len: usize) -> Self {
+        Self {
+            parent: (0..len).collect(),
+            size: vec![1; len],
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+
+        if a == b {
+            return;
+        }
+
+        let (a, b) = if self.size[a] < self.size[b] {
+            (b, a)
+        } else {
+            (a, b)
+        };
+
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Basin {
+    size: usize,
+    // Lowest cell, the first in reading order if several share its height
+    low: (u32, (usize, usize)),
+    // Cells at the lowest height, more than one for a flat floor
+    floor: usize,
+    // Inclusive bounding box corners
+    min: (usize, usize),
+    max: (usize, usize),
+}
+
+impl Basin {
+    fn new(n: u32, p: (usize, usize)) -> Self {
+        Self {
+            size: 1,
+            low: (n, p),
+            floor: 1,
+            min: p,
+            max: p,
+        }
+    }
+
+    fn add(&mut self, n: u32, (x, y): (usize, usize)) {
+        self.size += 1;
+
+        if n < self.low.0 {
+            self.low = (n, (x, y));
+            self.floor = 1;
+        } else if n == self.low.0 {
+            self.floor += 1;
+        }
+
+        self.min = (self.min.0.min(x), self.min.1.min(y));
+        self.max = (self.max.0.max(x), self.max.1.max(y));
+    }
+}
+
+#[derive(Debug)]
+struct Basins {
+    // Basin id of each cell, `None` for walls
+    labels: Vec<Vec<Option<usize>>>,
+    basins: Vec<Basin>,
+}
+
+impl Basins {
+    const SYMBOLS: &'static [u8] =
+        b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+    // One symbol per cell, cycling through the symbols by basin id
+    fn write_map(&self, w: &mut impl io::Write) -> io::Result<()> {
+        for row in &self.labels {
+            let line = row
+                .iter()
+                .map(|id| id.map_or('#', |id| Self::SYMBOLS[id % Self::SYMBOLS.len()] as char))
+                .collect::<String>();
+
+            writeln!(w, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Basins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "basin   size  low point  height  floor  bounds")?;
+
+        for (id, basin) in self.basins.iter().enumerate() {
+            let (height, (x, y)) = basin.low;
+
+            writeln!(
+                f,
+                "{:>5} {:>6}  {:>9}  {:>6}  {:>5}  {},{} - {},{}",
+                id,
+                basin.size,
+                format!("{},{}", x, y),
+                height,
+                basin.floor,
+                basin.min.0,
+                basin.min.1,
+                basin.max.0,
+                basin.max.1,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Terrain {
+    width: usize,
+    // Flattened in reading order like the cell indices below
+    heights: Vec<u32>,
+    // Next cell down the steepest descent or across a plateau, `None` at a
+    // sink
+    flow: Vec<Option<usize>>,
+    // Sink each cell eventually drains to
+    sinks: Vec<usize>,
+    // Walls bordering more than one basin
+    ridges: Vec<(usize, usize)>,
+    basins: Basins,
+}
+
+impl Terrain {
+    // Cells draining to each sink, largest watershed first
+    fn watersheds(&self) -> Vec<(usize, usize)> {
+        let mut watersheds = self
+            .sinks
+            .iter()
+            .copied()
+            .counts()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        watersheds.sort_unstable_by_key(|&(sink, cells)| (usize::MAX - cells, sink));
+        watersheds
+    }
+
+    // Arrow towards the next cell down, `·` for sinks
+    fn write_flow(&self, w: &mut impl io::Write) -> io::Result<()> {
+        for (y, row) in self.flow.chunks(self.width.max(1)).enumerate() {
+            let line = row
+                .iter()
+                .enumerate()
+                .map(|(x, next)| match next {
+                    None => '·',
+                    Some(next) => {
+                        let dx = (next % self.width) as isize - x as isize;
+                        let dy = (next / self.width) as isize - y as isize;
+
+                        match (dx, dy) {
+                            (-1, -1) => '↖',
+                            (0, -1) => '↑',
+                            (1, -1) => '↗',
+                            (-1, 0) => '←',
+                            (1, 0) => '→',
+                            (-1, 1) => '↙',
+                            (0, 1) => '↓',
+                            _ => '↘',
+                        }
+                    }
+                })
+                .collect::<String>();
+
+            writeln!(w, "{}", line)?;
+        }
+
+        Ok(())
+    }
+
+    // Ridges are listed in reading order
+    fn is_ridge(&self, x: usize, y: usize) -> bool {
+        self.ridges
+            .binary_search_by_key(&(y, x), |&(x, y)| (y, x))
+            .is_ok()
+    }
+
+    // Spreads basin hues around the colour wheel by the golden angle
+    fn hue(id: usize) -> [f64; 3] {
+        let h = (id as f64 * 0.618_033_988_75).fract() * 6.0;
+        let x = 1.0 - (h % 2.0 - 1.0).abs();
+
+        match h as u32 {
+            0 => [1.0, x, 0.0],
+            1 => [x, 1.0, 0.0],
+            2 => [0.0, 1.0, x],
+            3 => [0.0, x, 1.0],
+            4 => [x, 0.0, 1.0],
+            _ => [1.0, 0.0, x],
+        }
+    }
+
+    // Basins in their own hue, brighter as they rise, walls in grey and
+    // ridges in white
+    fn color(&self, i: usize, max: u32) -> [u8; 3] {
+        let (x, y) = (i % self.width, i / self.width);
+        let t = 0.3 + 0.7 * self.heights[i] as f64 / max.max(1) as f64;
+
+        match self.basins.labels[y][x] {
+            Some(id) => Self::hue(id).map(|c| (c * t * 255.0) as u8),
+            None if self.is_ridge(x, y) => [255, 255, 255],
+            None => [(t * 96.0) as u8; 3],
+        }
+    }
+
+    fn write_ppm(&self, w: &mut impl io::Write) -> io::Result<()> {
+        let max = self.heights.iter().copied().max().unwrap_or_default();
+
+        write!(w, "P6\n{} {}\n255\n", self.width, self.basins.labels.len())?;
+
+        for i in 0..self.heights.len() {
+            w.write_all(&self.color(i, max))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Terrain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let watersheds = self.watersheds();
+
+        writeln!(
+            f,
+            "{} basins, {} sinks, {} ridge cells",
+            self.basins.basins.len(),
+            watersheds.len(),
+            self.ridges.len()
+        )?;
+        writeln!(f, "     sink  height  cells  basin")?;
+
+        for (sink, cells) in watersheds {
+            let (x, y) = (sink % self.width, sink / self.width);
+
+            writeln!(
+                f,
+                "{:>9}  {:>6}  {:>5}  {:>5}",
+                format!("{},{}", x, y),
+                self.heights[sink],
+                cells,
+                self.basins.labels[y][x].map_or("-".to_string(), |id| id.to_string()),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// Optional `[4|8] [wall]` arguments
+fn flood(args: &mut impl Iterator<Item = String>) -> Result<Flood, Error> {
+    let mut flood = Flood::default();
+
+    if let Some(connectivity) = args.next() {
+        flood.connectivity = Connectivity::from_str(&connectivity)?;
+    }
+
+    if let Some(wall) = args.next() {
+        flood.wall = u32::from_str(&wall)?;
+    }
+
+    Ok(flood)
+}
+
 fn main() -> Result<(), Error> {
     let puzzle = Puzzle::from_str(&read_stdin()?)?;
+    let mut args = env::args().skip(1).peekable();
+
+    // `basins [table|map] [4|8] [wall]`
+    if args.next_if(|arg| arg == "basins").is_some() {
+        let format = args.next();
+        let flood = flood(&mut args)?;
+        let basins = puzzle.basins(&flood);
+
+        match format.as_deref() {
+            Some("map") => basins.write_map(&mut io::stdout().lock())?,
+            Some("table") | None => print!("{}", basins),
+            Some(format) => bail!("Unknown basins format: {}", format),
+        }
+
+        return Ok(());
+    }
+
+    // `fill <x> <y> [4|8] [wall]`
+    if args.next_if(|arg| arg == "fill").is_some() {
+        let x = usize::from_str(&args.next().context("Expected x")?)?;
+        let y = usize::from_str(&args.next().context("Expected y")?)?;
+        let flood = flood(&mut args)?;
+
+        println!("Basin size: {}", puzzle.basin_size(&flood, x, y));
+
+        return Ok(());
+    }
+
+    // `terrain [report|flow|ppm] [4|8] [wall]`
+    if args.next_if(|arg| arg == "terrain").is_some() {
+        let format = args.next();
+        let flood = flood(&mut args)?;
+        let terrain = puzzle.terrain(&flood);
+        let mut stdout = io::BufWriter::new(io::stdout().lock());
+
+        match format.as_deref() {
+            Some("flow") => terrain.write_flow(&mut stdout)?,
+            Some("ppm") => terrain.write_ppm(&mut stdout)?,
+            Some("report") | None => print!("{}", terrain),
+            Some(format) => bail!("Unknown terrain format: {}", format),
+        }
+
+        return Ok(());
+    }
 
     println!("Part 1: {}", puzzle.part_1());
     println!("Part 2: {}", puzzle.part_2()?);
@@ -128,6 +733,123 @@ mod test {
         assert_eq!(Puzzle::from_str(INPUT)?.part_2()?, 1134);
         Ok(())
     }
+
+    #[test]
+    fn basins() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str(INPUT)?;
+        let flood = Flood::default();
+        let basins = puzzle.basins(&flood);
+
+        assert_eq!(
+            basins.basins.iter().map(|b| b.size).collect::<Vec<_>>(),
+            [3, 9, 14, 9]
+        );
+        assert_eq!(basins.labels[0][0], Some(0));
+        assert_eq!(basins.labels[0][2], None);
+
+        for ((_, (x, y)), basin) in puzzle.low_points().zip(&basins.basins) {
+            assert_eq!(basin.low.1, (x, y));
+            assert_eq!(basin.size, puzzle.basin_size(&flood, x, y));
+        }
+
+        assert_eq!(basins.basins[2].min, (0, 1));
+        assert_eq!(basins.basins[2].max, (5, 4));
+
+        // A flat basin has no strict low point
+        let puzzle = Puzzle::from_str("1192\n1199\n9990")?;
+        let basins = puzzle.basins(&flood);
+
+        assert_eq!(puzzle.low_points().count(), 2);
+        assert_eq!(
+            basins.basins[0],
+            Basin {
+                size: 4,
+                low: (1, (0, 0)),
+                floor: 4,
+                min: (0, 0),
+                max: (1, 1),
+            }
+        );
+        assert_eq!(basins.basins.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn flood() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str(INPUT)?;
+        let eight = Flood {
+            connectivity: Connectivity::Eight,
+            wall: 9,
+        };
+
+        // Diagonal steps join all four basins
+        assert_eq!(puzzle.basin_size(&eight, 9, 0), 35);
+        assert_eq!(puzzle.basins(&eight).basins.len(), 1);
+
+        let low = Flood {
+            connectivity: Connectivity::Four,
+            wall: 5,
+        };
+
+        assert_eq!(puzzle.basin_size(&low, 9, 0), 9);
+        assert_eq!(puzzle.basins(&low).basins.len(), 2);
+        assert_eq!(puzzle.basin_size(&low, 2, 0), 0);
+
+        // One basin spanning a map deep enough to overflow a recursive fill
+        let puzzle = Puzzle {
+            heights: vec![vec![1; 1000]; 1000],
+        };
+
+        assert_eq!(puzzle.basin_size(&Flood::default(), 0, 0), 1_000_000);
+        Ok(())
+    }
+
+    #[test]
+    fn terrain() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str(INPUT)?;
+        let terrain = puzzle.terrain(&Flood::default());
+
+        // Walls drain too, so watersheds outgrow their basins
+        assert_eq!(terrain.watersheds(), [(22, 18), (9, 15), (46, 11), (1, 6)]);
+        assert_eq!(terrain.flow[0], Some(1));
+        assert_eq!(terrain.sinks[29], 9);
+        assert_eq!(terrain.ridges.len(), 11);
+        assert_eq!(terrain.ridges[..3], [(2, 0), (4, 0), (1, 1)]);
+
+        let mut ppm = Vec::new();
+
+        terrain.write_ppm(&mut ppm)?;
+
+        assert!(ppm.starts_with(b"P6\n10 5\n255\n"));
+        assert_eq!(ppm.len(), 12 + 10 * 5 * 3);
+        assert_eq!(ppm[12 + 2 * 3..][..3], [255, 255, 255]);
+
+        // A flat floor is one sink, and a shelf drains over its edge
+        let terrain = Puzzle::from_str("1192\n1199\n9990")?.terrain(&Flood::default());
+
+        assert_eq!(terrain.watersheds(), [(0, 8), (11, 3), (3, 1)]);
+        assert_eq!(terrain.flow[0], None);
+        assert_eq!(terrain.sinks[5], 0);
+
+        let terrain = Puzzle::from_str("3330")?.terrain(&Flood::default());
+        let mut flow = Vec::new();
+
+        terrain.write_flow(&mut flow)?;
+
+        assert_eq!(terrain.watersheds(), [(3, 4)]);
+        assert_eq!(String::from_utf8(flow)?, "→→→·\n");
+
+        let empty = Puzzle {
+            heights: Vec::new(),
+        }
+        .terrain(&Flood::default());
+        let mut flow = Vec::new();
+
+        empty.write_flow(&mut flow)?;
+
+        assert!(flow.is_empty());
+        Ok(())
+    }
 }
 /*
 client/web-api/src/api/
@@ -1,3 +1,5 @@
+use std::{env, fs};
+
 use util::*;
 
 #[derive(Debug)]
@@ -5,100 +7,879 @@ struct Puzzle {
     lines: Vec<String>,
 }
 
+// Columns count characters from 0
 #[derive(Debug)]
 enum NavError {
-    Corrupted(char),
-    Incomplete(Vec<char>),
-    Unknown(char),
+    // With the unmatched opener, if any
+    Corrupted {
+        close: char,
+        column: usize,
+        open: Option<(char, usize)>,
+    },
+    // Unclosed openers, innermost last
+    Incomplete(Vec<(char, usize)>),
+    Unknown {
+        c: char,
+        column: usize,
+    },
+    // Quote left open at the end of the line
+    Unterminated {
+        quote: char,
+        column: usize,
+    },
 }
 
+// Columns shown from 1
 impl fmt::Display for NavError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+        match self {
+            Self::Corrupted {
+                close,
+                column,
+                open: Some((open, from)),
+            } => write!(
+                f,
+                "Corrupted {} at column {} closes {} from column {}",
+                close,
+                column + 1,
+                open,
+                from + 1
+            ),
+            Self::Corrupted {
+                close,
+                column,
+                open: None,
+            } => write!(
+                f,
+                "Corrupted {} at column {} closes nothing",
+                close,
+                column + 1
+            ),
+            Self::Incomplete(stack) => write!(
+                f,
+                "Incomplete with {} open, outermost at column {}",
+                stack.len(),
+                stack.first().map_or(0, |(_, column)| column + 1)
+            ),
+            Self::Unknown { c, column } => write!(f, "Unknown {:?} at column {}", c, column + 1),
+            Self::Unterminated { quote, column } => {
+                write!(f, "Unterminated {} from column {}", quote, column + 1)
+            }
+        }
     }
 }
 
 impl error::Error for NavError {}
 
-fn corrupt_char_score(c: char) -> u64 {
-    match c {
-        ')' => 3,
-        ']' => 57,
-        '}' => 1197,
-        '>' => 25137,
-        _ => 0,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Token {
+    // Index into the grammar pairs
+    Open(usize),
+    Close(usize),
+    Ignore,
+    // Index into the grammar quotes
+    Quote(usize),
+}
+
+#[derive(Debug)]
+struct Pair {
+    open: char,
+    close: char,
+    // Points for a corrupted closer and for each completing closer
+    corrupt: u64,
+    incomplete: u64,
+}
+
+#[derive(Debug)]
+struct Quote {
+    quote: char,
+    // Skips the character after it inside the quote
+    escape: Option<char>,
+}
+
+// Bracket pairs and their scores, plus characters skipped outside brackets
+// and quotes whose contents are skipped entirely; anything else is unknown
+#[derive(Debug)]
+struct Grammar {
+    pairs: Vec<Pair>,
+    quotes: Vec<Quote>,
+    tokens: HashMap<char, Token>,
+    // Copy of the ASCII tokens, saving a hash per character
+    ascii: [Option<Token>; 128],
+}
+
+impl Grammar {
+    fn navigation() -> Self {
+        let mut grammar = Self {
+            pairs: Vec::new(),
+            quotes: Vec::new(),
+            tokens: HashMap::new(),
+            ascii: [None; 128],
+        };
+
+        for (open, close, corrupt, incomplete) in [
+            ('(', ')', 3, 1),
+            ('[', ']', 57, 2),
+            ('{', '}', 1197, 3),
+            ('<', '>', 25137, 4),
+        ] {
+            grammar
+                .add_pair(Pair {
+                    open,
+                    close,
+                    corrupt,
+                    incomplete,
+                })
+                .expect("Distinct pairs");
+        }
+
+        grammar
+    }
+
+    fn add_token(&mut self, c: char, token: Token) -> Result<(), Error> {
+        if self.tokens.insert(c, token).is_some() {
+            bail!("Character {:?} is defined twice", c);
+        }
+
+        if let Some(ascii) = self.ascii.get_mut(c as usize) {
+            *ascii = Some(token);
+        }
+
+        Ok(())
+    }
+
+    fn add_pair(&mut self, pair: Pair) -> Result<(), Error> {
+        self.add_token(pair.open, Token::Open(self.pairs.len()))?;
+        self.add_token(pair.close, Token::Close(self.pairs.len()))?;
+        self.pairs.push(pair);
+
+        Ok(())
+    }
+
+    fn add_quote(&mut self, quote: Quote) -> Result<(), Error> {
+        self.add_token(quote.quote, Token::Quote(self.quotes.len()))?;
+        self.quotes.push(quote);
+
+        Ok(())
+    }
+
+    fn token(&self, c: char) -> Option<&Token> {
+        match self.ascii.get(c as usize) {
+            Some(token) => token.as_ref(),
+            None => self.tokens.get(&c),
+        }
+    }
+
+    fn pair(&self, open: char) -> Option<&Pair> {
+        match self.token(open) {
+            Some(Token::Open(i)) => Some(&self.pairs[*i]),
+            _ => None,
+        }
+    }
+
+    fn corrupt_score(&self, close: char) -> u64 {
+        match self.token(close) {
+            Some(Token::Close(i)) => self.pairs[*i].corrupt,
+            _ => 0,
+        }
+    }
+
+    fn score_incomplete(&self, stack: Vec<(char, usize)>) -> u64 {
+        stack
+            .into_iter()
+            .rev()
+            .map(|(open, _)| self.pair(open).map_or(0, |pair| pair.incomplete))
+            .fold(0, |score, x| score * 5 + x)
+    }
+
+    // Index of the closing quote for the quote at `start`
+    fn quote_end(&self, quote: &Quote, chars: &[char], start: usize) -> Option<usize> {
+        let mut i = start + 1;
+
+        while i < chars.len() {
+            if chars[i] == quote.quote {
+                return Some(i);
+            }
+
+            i += if Some(chars[i]) == quote.escape { 2 } else { 1 };
+        }
+
+        None
+    }
+
+    fn check_line(&self, line: &str) -> Result<(), NavError> {
+        let chars = line.chars().collect::<Vec<_>>();
+        let mut stack = vec![];
+        let mut column = 0;
+
+        while let Some(&c) = chars.get(column) {
+            match self.token(c) {
+                Some(Token::Open(_)) => {
+                    stack.push((c, column));
+                }
+                Some(Token::Close(i)) => match stack.pop() {
+                    Some((open, _)) if open == self.pairs[*i].open => {}
+                    open => {
+                        return Err(NavError::Corrupted {
+                            close: c,
+                            column,
+                            open,
+                        })
+                    }
+                },
+                Some(Token::Ignore) => {}
+                Some(Token::Quote(i)) => {
+                    column = self
+                        .quote_end(&self.quotes[*i], &chars, column)
+                        .ok_or(NavError::Unterminated { quote: c, column })?;
+                }
+                None => return Err(NavError::Unknown { c, column }),
+            }
+
+            column += 1;
+        }
+
+        if stack.is_empty() {
+            Ok(())
+        } else {
+            Err(NavError::Incomplete(stack))
+        }
     }
+
+    // Fewest edits that leave the line merely incomplete. Ignored characters
+    // and terminated quotes never need editing, so only the other units are
+    // searched. `cost[i][j]` is the fewest edits balancing units `i..j`: the
+    // first unit is either dropped or opens a pair closed by a later one,
+    // replacing either end if they do not fit. Openers left open are only
+    // completed, so a pass over the last one left open finishes the line,
+    // unless closing an unterminated quote at the end is cheaper. Inserting
+    // anything else never beats dropping the unit it would pair with.
+    // O(n³) in the units, so lines without errors skip the search
+    fn repair(&self, line: &str) -> Repair {
+        let chars = line.chars().collect::<Vec<_>>();
+        let mut units = Vec::new();
+        let mut column = 0;
+
+        while let Some(&c) = chars.get(column) {
+            match self.token(c) {
+                Some(Token::Open(i)) => units.push((column, c, Role::Open(*i))),
+                Some(Token::Close(i)) => units.push((column, c, Role::Close(*i))),
+                Some(Token::Ignore) => {}
+                Some(Token::Quote(i)) => match self.quote_end(&self.quotes[*i], &chars, column) {
+                    Some(end) => column = end,
+                    None => units.push((column, c, Role::Stray)),
+                },
+                None => units.push((column, c, Role::Stray)),
+            }
+
+            column += 1;
+        }
+
+        let mut stack = Vec::new();
+        let valid = units.iter().all(|(_, _, role)| match role {
+            Role::Open(i) => {
+                stack.push(*i);
+                true
+            }
+            Role::Close(i) => stack.pop() == Some(*i),
+            Role::Stray => false,
+        });
+
+        if valid {
+            return Repair {
+                line: chars,
+                edits: Vec::new(),
+                completion: stack.iter().rev().map(|i| self.pairs[*i].close).collect(),
+            };
+        }
+
+        let n = units.len();
+        let at = |i: usize, j: usize| i * (n + 1) + j;
+
+        // Edits making units `i` and `k` a pair, and which pair
+        let pairing = |i: usize, k: usize| match (units[i].2, units[k].2) {
+            (Role::Open(p), Role::Close(q)) if p == q => Some((0, p)),
+            (Role::Open(p), _) => Some((1, p)),
+            (_, Role::Close(q)) => Some((1, q)),
+            _ => (!self.pairs.is_empty()).then_some((2, 0)),
+        };
+
+        let mut cost = vec![0; at(n, n) + 1];
+
+        for len in 1..=n {
+            for i in 0..=n - len {
+                let j = i + len;
+                let paired = (i + 1..j).filter_map(|k| {
+                    let (edits, _) = pairing(i, k)?;
+
+                    Some(edits + cost[at(i + 1, k)] + cost[at(k + 1, j)])
+                });
+
+                cost[at(i, j)] = paired.fold(1 + cost[at(i + 1, j)], usize::min);
+            }
+        }
+
+        // Fewest edits for units `..j`, with the last opener left open
+        let mut prefix = vec![(0, None); n + 1];
+
+        for j in 1..=n {
+            let mut best = (cost[at(0, j)], None);
+
+            for k in 0..j {
+                let reopened = usize::from(!matches!(units[k].2, Role::Open(_)));
+                let edits = prefix[k].0 + reopened + cost[at(k + 1, j)];
+
+                if edits < best.0 {
+                    best = (edits, Some(k));
+                }
+            }
+
+            prefix[j] = best;
+        }
+
+        // Units from an unterminated quote on are left inside it
+        let mut kept = n;
+        let mut closed = None;
+        let mut fewest = prefix[n].0;
+
+        for (k, (_, c, role)) in units.iter().enumerate() {
+            if let (Role::Stray, Some(Token::Quote(i))) = (role, self.token(*c)) {
+                if prefix[k].0 + 1 < fewest {
+                    fewest = prefix[k].0 + 1;
+                    kept = k;
+                    closed = Some(self.quotes[*i].quote);
+                }
+            }
+        }
+
+        let mut edits = Vec::new();
+        let replace = |k: usize, to: char| {
+            let (column, from, _) = units[k];
+
+            (from != to).then_some(Edit::Replace { column, from, to })
+        };
+
+        // Openers left open, innermost first, each after a balanced stretch
+        let mut open = Vec::new();
+        let mut stretches = Vec::new();
+        let mut j = kept;
+
+        while let (_, Some(k)) = prefix[j] {
+            let i = match units[k].2 {
+                Role::Open(i) => i,
+                _ => 0,
+            };
+
+            edits.extend(replace(k, self.pairs[i].open));
+            open.push(i);
+            stretches.push((k + 1, j));
+            j = k;
+        }
+
+        stretches.push((0, j));
+
+        while let Some((i, j)) = stretches.pop() {
+            if i == j {
+                continue;
+            }
+
+            if cost[at(i, j)] == 1 + cost[at(i + 1, j)] {
+                let (column, c, _) = units[i];
+
+                edits.push(Edit::Delete { column, c });
+                stretches.push((i + 1, j));
+                continue;
+            }
+
+            let (k, p) = (i + 1..j)
+                .find_map(|k| {
+                    let (edits, p) = pairing(i, k)?;
+                    let paired = edits + cost[at(i + 1, k)] + cost[at(k + 1, j)];
+
+                    (paired == cost[at(i, j)]).then_some((k, p))
+                })
+                .expect("Balanced by dropping or pairing the first unit");
+
+            edits.extend(replace(i, self.pairs[p].open));
+            edits.extend(replace(k, self.pairs[p].close));
+            stretches.push((i + 1, k));
+            stretches.push((k + 1, j));
+        }
+
+        if let Some(c) = closed {
+            edits.push(Edit::Insert {
+                column: chars.len(),
+                c,
+            });
+        }
+
+        edits.sort_by_key(Edit::column);
+
+        Repair {
+            line: chars,
+            edits,
+            completion: open.iter().map(|i| self.pairs[*i].close).collect(),
+        }
+    }
+}
+
+// How a character that may need editing takes part in a repair
+#[derive(Clone, Copy, Debug)]
+enum Role {
+    Open(usize),
+    Close(usize),
+    // Unknown, or a quote that never ends
+    Stray,
+}
+
+// Columns in the original line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Edit {
+    Delete { column: usize, c: char },
+    Replace { column: usize, from: char, to: char },
+    // Before the character at `column`
+    Insert { column: usize, c: char },
 }
 
-fn incomplete_char_score(c: char) -> u64 {
-    match c {
-        '(' => 1,
-        '[' => 2,
-        '{' => 3,
-        '<' => 4,
-        _ => 0,
+impl Edit {
+    fn column(&self) -> usize {
+        match self {
+            Self::Delete { column, .. }
+            | Self::Replace { column, .. }
+            | Self::Insert { column, .. } => *column,
+        }
     }
 }
 
-fn is_match(open: char, close: char) -> bool {
-    match open {
-        '(' => close == ')',
-        '[' => close == ']',
-        '{' => close == '}',
-        '<' => close == '>',
-        _ => false,
+impl fmt::Display for Edit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Delete { column, c } => write!(f, "column {}: delete {}", column + 1, c),
+            Self::Replace { column, from, to } => {
+                write!(f, "column {}: replace {} with {}", column + 1, from, to)
+            }
+            Self::Insert { column, c } => write!(f, "column {}: insert {}", column + 1, c),
+        }
     }
 }
 
-fn check_line(line: &str) -> Result<(), NavError> {
-    let mut stack = vec![];
+#[derive(Debug)]
+struct Repair {
+    line: Vec<char>,
+    // In column order
+    edits: Vec<Edit>,
+    // Closers appended once the edits leave the line incomplete
+    completion: String,
+}
 
-    for c in line.chars() {
-        match c {
-            '(' | '[' | '{' | '<' => {
-                stack.push(c);
-            }
-            ')' | ']' | '}' | '>' => {
-                if !stack.pop().map(|open| is_match(open, c)).unwrap_or(false) {
-                    return Err(NavError::Corrupted(c));
+impl Repair {
+    fn repaired(&self) -> String {
+        let mut edits = self.edits.iter().peekable();
+        let mut repaired = String::new();
+
+        for column in 0..=self.line.len() {
+            let mut keep = true;
+
+            while let Some(edit) = edits.next_if(|edit| edit.column() == column) {
+                match edit {
+                    Edit::Insert { c, .. } => repaired.push(*c),
+                    Edit::Replace { to, .. } => {
+                        repaired.push(*to);
+                        keep = false;
+                    }
+                    Edit::Delete { .. } => keep = false,
                 }
             }
-            c => return Err(NavError::Unknown(c)),
+
+            if let Some(c) = self.line.get(column).filter(|_| keep) {
+                repaired.push(*c);
+            }
         }
+
+        repaired.push_str(&self.completion);
+        repaired
     }
+}
+
+// Diff style, with the edits and completion listed under the lines
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "- {}", self.line.iter().collect::<String>())?;
+        writeln!(f, "+ {}", self.repaired())?;
+
+        for edit in &self.edits {
+            writeln!(f, "  {}", edit)?;
+        }
+
+        if !self.completion.is_empty() {
+            writeln!(f, "  complete with {}", self.completion)?;
+        }
 
-    if stack.is_empty() {
         Ok(())
-    } else {
-        Err(NavError::Incomplete(stack))
     }
 }
 
-fn score_incomplete(stack: Vec<char>) -> u64 {
-    stack
-        .into_iter()
-        .rev()
-        .map(incomplete_char_score)
-        .fold(0, |score, x| score * 5 + x)
+impl Default for Grammar {
+    fn default() -> Self {
+        Self::navigation()
+    }
+}
+
+// `space` and `tab` name the blank characters
+fn parse_char(s: &str) -> Result<char, Error> {
+    match s {
+        "space" => Ok(' '),
+        "tab" => Ok('\t'),
+        _ => s
+            .chars()
+            .exactly_one()
+            .map_err(|_| Error::msg(format!("Expected one character: {}", s))),
+    }
+}
+
+impl FromStr for Grammar {
+    type Err = Error;
+
+    // One definition per line, `#` starts a comment:
+    // `pair <open> <close> <corrupt score> <incomplete score>`,
+    // `ignore <char>...` and `quote <char> [escape]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut grammar = Self {
+            pairs: Vec::new(),
+            quotes: Vec::new(),
+            tokens: HashMap::new(),
+            ascii: [None; 128],
+        };
+
+        for line in s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        {
+            let mut words = line.split_whitespace();
+
+            match words.next() {
+                Some("pair") => {
+                    let (open, close, corrupt, incomplete) = words
+                        .collect_tuple()
+                        .with_context(|| format!("Expected open, close and scores: {}", line))?;
+
+                    grammar.add_pair(Pair {
+                        open: parse_char(open)?,
+                        close: parse_char(close)?,
+                        corrupt: u64::from_str(corrupt)?,
+                        incomplete: u64::from_str(incomplete)?,
+                    })?;
+                }
+                Some("ignore") => {
+                    for c in words {
+                        grammar.add_token(parse_char(c)?, Token::Ignore)?;
+                    }
+                }
+                Some("quote") => {
+                    let quote = parse_char(words.next().context("Expected quote")?)?;
+                    let escape = words.next().map(parse_char).transpose()?;
+
+                    grammar.add_quote(Quote { quote, escape })?;
+                }
+                _ => bail!("Unknown definition: {}", line),
+            }
+        }
+
+        if grammar.pairs.is_empty() {
+            bail!("Expected pairs");
+        }
+
+        Ok(grammar)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Summary {
+    // Non-empty lines, or the whole input as one
+    records: u64,
+    valid: u64,
+    corrupted: u64,
+    incomplete: u64,
+    unknown: u64,
+    unterminated: u64,
+    corrupted_score: u64,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Records: {}", self.records)?;
+        writeln!(f, "Valid: {}", self.valid)?;
+        writeln!(f, "Corrupted: {}", self.corrupted)?;
+        writeln!(f, "Incomplete: {}", self.incomplete)?;
+        writeln!(f, "Unknown: {}", self.unknown)?;
+        writeln!(f, "Unterminated: {}", self.unterminated)?;
+        writeln!(f, "Corrupted score: {}", self.corrupted_score)
+    }
+}
+
+// Checks characters as they arrive, holding only the open brackets. Split
+// on newlines it matches `Grammar::check_line` on trimmed lines; as one
+// stream undefined whitespace is skipped, columns count characters from the
+// start of the input and checking carries on past errors, as if a corrupted
+// closer had closed the innermost opener
+struct Validator<'a> {
+    grammar: &'a Grammar,
+    lines: bool,
+    stack: Vec<(char, usize)>,
+    // Open quote index and column, and whether the next character is escaped
+    quote: Option<(usize, usize)>,
+    escaped: bool,
+    // First of the blanks since the last character, trimmed at a line end
+    blank: Option<(char, usize)>,
+    // Whether the record has any characters or errors, and whether a line
+    // is skipped after its first error
+    started: bool,
+    clean: bool,
+    failed: bool,
+    // Line of the current character, from 1
+    line: usize,
+    column: usize,
+    summary: Summary,
+}
+
+impl<'a> Validator<'a> {
+    fn new(grammar: &'a Grammar, lines: bool) -> Self {
+        Self {
+            grammar,
+            lines,
+            stack: Vec::new(),
+            quote: None,
+            escaped: false,
+            blank: None,
+            started: false,
+            clean: true,
+            failed: false,
+            line: 1,
+            column: 0,
+            summary: Summary::default(),
+        }
+    }
+
+    // Reports each error with the line it was found on
+    fn validate(
+        mut self,
+        reader: impl io::Read,
+        mut report: impl FnMut(usize, NavError) -> io::Result<()>,
+    ) -> io::Result<Summary> {
+        let mut reader = io::BufReader::with_capacity(1 << 16, reader);
+        // Bytes of a character split across reads
+        let mut partial = Vec::with_capacity(4);
+
+        loop {
+            let chunk = io::BufRead::fill_buf(&mut reader)?;
+
+            if chunk.is_empty() {
+                break;
+            }
+
+            for &b in chunk {
+                if partial.is_empty() && b.is_ascii() {
+                    self.push(char::from(b), &mut report)?;
+                    continue;
+                }
+
+                partial.push(b);
+
+                match str::from_utf8(&partial) {
+                    Ok(s) => {
+                        let c = s.chars().next().expect("One character");
+
+                        partial.clear();
+                        self.push(c, &mut report)?;
+                    }
+                    Err(err) if err.error_len().is_none() => {}
+                    Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+                }
+            }
+
+            let len = chunk.len();
+
+            io::BufRead::consume(&mut reader, len);
+        }
+
+        if !partial.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Input ends inside a character",
+            ));
+        }
+
+        self.end(&mut report)?;
+
+        Ok(self.summary)
+    }
+
+    fn push(
+        &mut self,
+        c: char,
+        report: &mut impl FnMut(usize, NavError) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let defined = self.grammar.token(c).is_some();
+
+        if c == '\n' {
+            if self.lines {
+                self.end(report)?;
+            }
+
+            self.line += 1;
+
+            if self.lines {
+                return Ok(());
+            }
+        }
+
+        if self.failed {
+            return Ok(());
+        }
+
+        if let Some((i, _)) = self.quote {
+            let quote = &self.grammar.quotes[i];
+
+            if self.escaped {
+                self.escaped = false;
+            } else if c == quote.quote {
+                self.quote = None;
+            } else if Some(c) == quote.escape {
+                self.escaped = true;
+            }
+
+            self.column += 1;
+
+            return Ok(());
+        }
+
+        // Quoted blanks are handled above, whatever the mode
+        if !self.lines && !defined && c.is_ascii_whitespace() {
+            self.column += 1;
+
+            return Ok(());
+        }
+
+        if !defined && c.is_ascii_whitespace() {
+            if self.started {
+                self.blank.get_or_insert((c, self.column));
+                self.column += 1;
+            }
+
+            return Ok(());
+        }
+
+        self.started = true;
+
+        if let Some((c, column)) = self.blank.take() {
+            self.fail(NavError::Unknown { c, column }, report)?;
+
+            if self.failed {
+                return Ok(());
+            }
+        }
+
+        let column = self.column;
+
+        self.column += 1;
+
+        match self.grammar.token(c) {
+            Some(Token::Open(_)) => self.stack.push((c, column)),
+            Some(Token::Close(i)) => match self.stack.pop() {
+                Some((open, _)) if open == self.grammar.pairs[*i].open => {}
+                open => {
+                    let error = NavError::Corrupted {
+                        close: c,
+                        column,
+                        open,
+                    };
+
+                    self.fail(error, report)?;
+                }
+            },
+            Some(Token::Ignore) => {}
+            Some(Token::Quote(i)) => self.quote = Some((*i, column)),
+            None => self.fail(NavError::Unknown { c, column }, report)?,
+        }
+
+        Ok(())
+    }
+
+    fn fail(
+        &mut self,
+        error: NavError,
+        report: &mut impl FnMut(usize, NavError) -> io::Result<()>,
+    ) -> io::Result<()> {
+        match &error {
+            NavError::Corrupted { close, .. } => {
+                self.summary.corrupted += 1;
+                self.summary.corrupted_score += self.grammar.corrupt_score(*close);
+            }
+            NavError::Incomplete(_) => self.summary.incomplete += 1,
+            NavError::Unknown { .. } => self.summary.unknown += 1,
+            NavError::Unterminated { .. } => self.summary.unterminated += 1,
+        }
+
+        self.clean = false;
+        self.failed = self.lines;
+
+        report(self.line, error)
+    }
+
+    // Closes a line, or the whole input
+    fn end(
+        &mut self,
+        report: &mut impl FnMut(usize, NavError) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if self.started || !self.lines {
+            self.summary.records += 1;
+
+            if self.failed {
+                // Already reported
+            } else if let Some((i, column)) = self.quote {
+                let quote = self.grammar.quotes[i].quote;
+
+                self.fail(NavError::Unterminated { quote, column }, report)?;
+            } else if !self.stack.is_empty() {
+                let stack = std::mem::take(&mut self.stack);
+
+                self.fail(NavError::Incomplete(stack), report)?;
+            } else if self.clean {
+                self.summary.valid += 1;
+            }
+        }
+
+        self.stack.clear();
+        self.quote = None;
+        self.escaped = false;
+        self.blank = None;
+        self.started = false;
+        self.clean = true;
+        self.failed = false;
+        self.column = 0;
+
+        Ok(())
+    }
 }
 
 impl Puzzle {
-    fn part_1(&self) -> u64 {
+    fn corrupted_score(&self, grammar: &Grammar) -> u64 {
         self.lines
             .iter()
-            .filter_map(|line| match check_line(line) {
-                Err(NavError::Corrupted(c)) => Some(corrupt_char_score(c)),
+            .filter_map(|line| match grammar.check_line(line) {
+                Err(NavError::Corrupted { close, .. }) => Some(grammar.corrupt_score(close)),
                 _ => None,
             })
             .sum()
     }
 
-    fn part_2(&self) -> Result<u64, Error> {
+    fn incomplete_score(&self, grammar: &Grammar) -> Result<u64, Error> {
         let mut scores = self
             .lines
             .iter()
-            .filter_map(|line| match check_line(line) {
-                Err(NavError::Incomplete(stack)) => Some(score_incomplete(stack)),
+            .filter_map(|line| match grammar.check_line(line) {
+                Err(NavError::Incomplete(stack)) => Some(grammar.score_incomplete(stack)),
                 _ => None,
             })
             .collect::<Vec<_>>();
@@ -111,6 +892,14 @@ impl Puzzle {
 
         Ok(scores[scores.len() / 2])
     }
+
+    fn part_1(&self) -> u64 {
+        self.corrupted_score(&Grammar::default())
+    }
+
+    fn part_2(&self) -> Result<u64, Error> {
+        self.incomplete_score(&Grammar::default())
+    }
 }
 
 impl From<&str> for Puzzle {
@@ -126,8 +915,79 @@ impl From<&str> for Puzzle {
 }
 
 fn main() -> Result<(), Error> {
+    let mut args = env::args().skip(1).peekable();
+    let repair = args.next_if(|arg| arg == "repair").is_some();
+    // `stream <lines|whole>` validates stdin without reading it all first
+    let stream = match args.next_if(|arg| arg == "stream") {
+        Some(_) => Some(args.next().context("Expected lines or whole")?),
+        None => None,
+    };
+
+    // Optional grammar definition file, otherwise the navigation brackets
+    let grammar = match args.next() {
+        Some(path) => Some(Grammar::from_str(&fs::read_to_string(path)?)?),
+        None => None,
+    };
+
+    if let Some(mode) = stream {
+        let lines = match mode.as_str() {
+            "lines" => true,
+            "whole" => false,
+            _ => bail!("Unknown stream mode: {}", mode),
+        };
+        let grammar = grammar.unwrap_or_default();
+        let mut stdout = io::BufWriter::new(io::stdout().lock());
+        let summary = Validator::new(&grammar, lines)
+            .validate(io::stdin().lock(), |line, err| {
+                io::Write::write_fmt(&mut stdout, format_args!("Line {}: {}\n", line, err))
+            })?;
+
+        drop(stdout);
+        print!("{}", summary);
+
+        return Ok(());
+    }
+
     let puzzle = Puzzle::from(read_stdin()?.as_str());
 
+    if repair {
+        let grammar = grammar.unwrap_or_default();
+
+        for (i, line) in puzzle.lines.iter().enumerate() {
+            if let Err(err) = grammar.check_line(line) {
+                println!("Line {}: {}", i + 1, err);
+                println!("{}", grammar.repair(line));
+            }
+        }
+
+        return Ok(());
+    }
+
+    // Scores the lines against the grammar in a definition file
+    if let Some(grammar) = grammar {
+        for (i, line) in puzzle.lines.iter().enumerate() {
+            match grammar.check_line(line) {
+                Err(NavError::Unknown { c, column }) => {
+                    println!("Line {}: unknown {:?} at column {}", i + 1, c, column + 1)
+                }
+                Err(NavError::Unterminated { quote, column }) => {
+                    println!(
+                        "Line {}: unterminated {} at column {}",
+                        i + 1,
+                        quote,
+                        column + 1
+                    )
+                }
+                _ => {}
+            }
+        }
+
+        println!("Corrupted: {}", puzzle.corrupted_score(&grammar));
+        println!("Incomplete: {}", puzzle.incomplete_score(&grammar)?);
+
+        return Ok(());
+    }
+
     println!("Part 1: {}", puzzle.part_1());
     println!("Part 2: {}", puzzle.part_2()?);
 
@@ -136,6 +996,8 @@ fn main() -> Result<(), Error> {
 
 #[cfg(test)]
 mod test {
+    use std::time;
+
     use super::*;
 
     static INPUT: &str = r#"
@@ -162,4 +1024,185 @@ mod test {
         assert_eq!(Puzzle::from(INPUT).part_2()?, 288957);
         Ok(())
     }
+
+    #[test]
+    fn grammar() -> Result<(), Error> {
+        let grammar = Grammar::from_str(
+            r#"
+            # Parentheses and guillemets, with strings and spacing
+            pair ( ) 2 1
+            pair « » 5 3
+            ignore space ,
+            quote " \
+            "#,
+        )?;
+
+        assert!(grammar.check_line("(«, »)").is_ok());
+        assert!(grammar.check_line(r#"( "»)\" )" )"#).is_ok());
+        assert!(matches!(
+            grammar.check_line("(«)"),
+            Err(NavError::Corrupted { close: ')', .. })
+        ));
+        assert!(matches!(
+            grammar.check_line("( ["),
+            Err(NavError::Unknown { c: '[', column: 2 })
+        ));
+        assert!(matches!(
+            grammar.check_line(r#"(" "#),
+            Err(NavError::Unterminated {
+                quote: '"',
+                column: 1
+            })
+        ));
+
+        let puzzle = Puzzle::from("((«)\n(«\n«( \"\" )»");
+
+        assert_eq!(puzzle.corrupted_score(&grammar), 2);
+        assert_eq!(puzzle.incomplete_score(&grammar)?, 3 * 5 + 1);
+        assert!(Grammar::from_str("pair ( ( 1 1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn repair() -> Result<(), Error> {
+        let grammar = Grammar::default();
+        let line = "{([(<{}[<>[]}>{[]{[(<()>";
+
+        assert!(matches!(
+            grammar.check_line(line),
+            Err(NavError::Corrupted {
+                close: '}',
+                column: 12,
+                open: Some(('[', 7))
+            })
+        ));
+
+        let repair = grammar.repair(line);
+
+        assert_eq!(repair.edits.len(), 1);
+        assert_eq!(
+            grammar.check_line(&repair.repaired()).ok(),
+            Some(()),
+            "{}",
+            repair
+        );
+
+        // Incomplete lines are only completed
+        let repair = grammar.repair("[({(<(())[]>[[{[]{<()<>>");
+
+        assert!(repair.edits.is_empty());
+        assert_eq!(repair.completion, "}}]])})]");
+
+        // Stray closer at the start
+        let repair = grammar.repair(")()");
+
+        assert_eq!(repair.edits, [Edit::Delete { column: 0, c: ')' }]);
+        assert_eq!(repair.repaired(), "()");
+
+        // Either drop the opener or close it
+        let repair = grammar.repair("[(]");
+
+        assert_eq!(repair.edits.len(), 1);
+        assert!(grammar.check_line(&repair.repaired()).is_ok());
+
+        let repair = grammar.repair("([)]");
+
+        assert_eq!(repair.edits.len(), 1);
+        assert!(grammar.check_line(&repair.repaired()).is_ok());
+
+        // Stray closers turned into openers
+        let repair = grammar.repair("))");
+
+        assert_eq!(repair.edits.len(), 1);
+        assert_eq!(repair.repaired(), "()");
+
+        let repair = grammar.repair("}]>");
+
+        assert_eq!(repair.edits.len(), 2);
+        assert!(grammar.check_line(&repair.repaired()).is_ok());
+
+        // Polynomial however many edits the line needs
+        let started = time::Instant::now();
+        let repair = grammar.repair(&")".repeat(40));
+
+        assert!(started.elapsed() < time::Duration::from_secs(1));
+        assert_eq!(repair.edits.len(), 20);
+        assert!(grammar.check_line(&repair.repaired()).is_ok());
+
+        // Closing the quote keeps its unknown contents
+        let grammar = Grammar::from_str("pair ( ) 1 1\nquote '")?;
+
+        assert_eq!(grammar.repair("(')").repaired(), "()");
+        assert_eq!(grammar.repair("('x").repaired(), "('x')");
+        Ok(())
+    }
+
+    #[test]
+    fn stream() -> Result<(), Error> {
+        let grammar = Grammar::default();
+        let mut errors = Vec::new();
+        let summary = Validator::new(&grammar, true).validate(INPUT.as_bytes(), |line, err| {
+            errors.push((line, err.to_string()));
+            Ok(())
+        })?;
+
+        // Same errors as checking each trimmed line
+        let expected = INPUT
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| Some((i + 1, grammar.check_line(line.trim()).err()?)))
+            .map(|(line, err)| (line, err.to_string()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(errors, expected);
+        assert_eq!(summary.records, 10);
+        assert_eq!(summary.corrupted, 5);
+        assert_eq!(summary.incomplete, 5);
+        assert_eq!(summary.corrupted_score, 26397);
+
+        // As one stream, errors past the first are still found
+        let mut errors = Vec::new();
+        let summary = Validator::new(&grammar, false).validate(
+            "(\n  [>\n  ]\n)x)".as_bytes(),
+            |line, err| {
+                errors.push(format!("{}: {}", line, err));
+                Ok(())
+            },
+        )?;
+
+        assert_eq!(
+            errors,
+            [
+                "2: Corrupted > at column 6 closes [ from column 5",
+                "3: Corrupted ] at column 10 closes ( from column 1",
+                "4: Corrupted ) at column 12 closes nothing",
+                "4: Unknown 'x' at column 13",
+                "4: Corrupted ) at column 14 closes nothing",
+            ]
+        );
+        assert_eq!(summary.valid, 0);
+
+        // Characters split across reads
+        let grammar = Grammar::from_str("pair « » 1 1")?;
+        let input = "««»»".as_bytes();
+        let summary = Validator::new(&grammar, true)
+            .validate(io::Read::chain(&input[..3], &input[3..]), |_, _| Ok(()))?;
+
+        assert_eq!(summary.valid, 1);
+
+        // An escaped blank inside a quote is still just escaped
+        let grammar = Grammar::from_str("pair ( ) 1 1\nquote \" \\")?;
+        let line = r#"("\ ")"#;
+
+        assert!(grammar.check_line(line).is_ok());
+
+        for lines in [true, false] {
+            let summary =
+                Validator::new(&grammar, lines).validate(line.as_bytes(), |_, _| Ok(()))?;
+
+            assert_eq!(summary.valid, 1);
+        }
+
+        Ok(())
+    }
 }
@@ -1,3 +1,5 @@
+use std::env;
+
 use util::*;
 
 #[derive(Debug)]
@@ -9,111 +11,466 @@ impl FromStr for Puzzle {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self {
-            energy: parse_grid(s)?,
-        })
+        let energy = parse_grid(s)?;
+
+        if energy.iter().map(Vec::len).dedup().count() > 1 {
+            bail!("Rows must have equal width");
+        }
+
+        Ok(Self { energy })
     }
 }
 
 impl Puzzle {
-    fn simulation(&self, energy: &mut Vec<Vec<u64>>) -> u64 {
-        fn run(energy: &mut Vec<Vec<u64>>, x: usize, y: usize) -> u64 {
-            if y >= energy.len() {
-                return 0;
-            }
+    fn automaton(&self, rules: Rules) -> Automaton {
+        Automaton::new(
+            self.energy.first().map_or(0, Vec::len),
+            self.energy.concat(),
+            rules,
+        )
+    }
 
-            let row = &mut energy[y];
+    fn part_1(&self) -> usize {
+        let mut automaton = self.automaton(Rules::default());
 
-            if x >= row.len() {
-                return 0;
-            }
+        (0..100)
+            .map(|_| automaton.step())
+            .last()
+            .map_or(0, |step| step.total)
+    }
 
-            let n = &mut row[x];
+    fn part_2(&self) -> Result<usize, Error> {
+        let mut automaton = self.automaton(Rules::default());
 
-            *n += 1;
-            if *n != 10 {
-                return 0;
-            }
+        match automaton.synchronise(Automaton::MAX_STEPS)? {
+            Outcome::Synchronised(step) => Ok(step),
+            outcome => bail!("Never synchronises: {}", outcome),
+        }
+    }
+}
 
-            let mut total = 1;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Neighbourhood {
+    // Orthogonal neighbours only
+    VonNeumann,
+    // Diagonals as well
+    Moore,
+}
 
-            if x > 0 {
-                total += run(energy, x - 1, y);
-                total += run(energy, x - 1, y + 1);
-            }
-            if y > 0 {
-                total += run(energy, x, y - 1);
-                total += run(energy, x + 1, y - 1);
-            }
-            if x > 0 && y > 0 {
-                total += run(energy, x - 1, y - 1);
+impl Neighbourhood {
+    fn offsets(self) -> &'static [(isize, isize)] {
+        match self {
+            Self::VonNeumann => &[(0, -1), (-1, 0), (1, 0), (0, 1)],
+            Self::Moore => &[
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (-1, 0),
+                (1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1),
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Cascade {
+    // Flashes leave their neighbours alone
+    Off,
+    // Each flash gives every neighbour this much energy
+    Add(u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Reset {
+    Zero,
+    // Keeps the energy above the threshold
+    Subtract,
+    Value(u64),
+}
+
+// Every step each cell gains energy, cells reaching the threshold flash at
+// most once each, possibly cascading into their neighbours, and flashed
+// cells are reset at the end of the step
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Rules {
+    increment: u64,
+    threshold: u64,
+    neighbourhood: Neighbourhood,
+    // Whether neighbours wrap around the grid edges
+    wrap: bool,
+    cascade: Cascade,
+    reset: Reset,
+}
+
+// The octopus rules
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            increment: 1,
+            threshold: 10,
+            neighbourhood: Neighbourhood::Moore,
+            wrap: false,
+            cascade: Cascade::Add(1),
+            reset: Reset::Zero,
+        }
+    }
+}
+
+impl FromStr for Rules {
+    type Err = Error;
+
+    // Comma separated `key=value` changes to the octopus rules: `increment`,
+    // `threshold`, `neighbourhood` (`4` or `8`), `wrap` (`true` or `false`),
+    // `cascade` (`off` or energy) and `reset` (`zero`, `subtract` or energy)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = Self::default();
+
+        for change in s.split(',').filter(|change| !change.is_empty()) {
+            let (key, value) = change
+                .split_once('=')
+                .with_context(|| format!("Expected key=value: {}", change))?;
+
+            match key {
+                "increment" => rules.increment = u64::from_str(value)?,
+                "threshold" => rules.threshold = u64::from_str(value)?,
+                "neighbourhood" => {
+                    rules.neighbourhood = match value {
+                        "4" => Neighbourhood::VonNeumann,
+                        "8" => Neighbourhood::Moore,
+                        _ => bail!("Unknown neighbourhood: {}", value),
+                    }
+                }
+                "wrap" => rules.wrap = bool::from_str(value)?,
+                "cascade" => {
+                    rules.cascade = match value {
+                        "off" => Cascade::Off,
+                        _ => Cascade::Add(u64::from_str(value)?),
+                    }
+                }
+                "reset" => {
+                    rules.reset = match value {
+                        "zero" => Reset::Zero,
+                        "subtract" => Reset::Subtract,
+                        _ => Reset::Value(u64::from_str(value)?),
+                    }
+                }
+                _ => bail!("Unknown rule: {}", key),
             }
+        }
 
-            total += run(energy, x + 1, y);
-            total += run(energy, x, y + 1);
-            total += run(energy, x + 1, y + 1);
+        if rules.threshold == 0 {
+            bail!("Threshold must be positive");
+        }
 
-            total
+        Ok(rules)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Automaton {
+    width: usize,
+    // Row major
+    cells: Vec<u64>,
+    rules: Rules,
+    // Neighbour indices of each cell, worked out once
+    neighbours: Vec<Vec<usize>>,
+    // Steps taken and flashes over all of them
+    steps: usize,
+    total: usize,
+}
+
+impl Automaton {
+    // Bound on the search for synchronisation
+    const MAX_STEPS: usize = 1_000_000;
+
+    fn new(width: usize, cells: Vec<u64>, rules: Rules) -> Self {
+        let height = cells.len().checked_div(width).unwrap_or(0);
+        let neighbours = (0..cells.len())
+            .map(|i| {
+                let (x, y) = ((i % width) as isize, (i / width) as isize);
+                let (w, h) = (width as isize, height as isize);
+
+                rules
+                    .neighbourhood
+                    .offsets()
+                    .iter()
+                    .filter_map(|(dx, dy)| {
+                        let (mut x, mut y) = (x + dx, y + dy);
+
+                        if rules.wrap {
+                            x = x.rem_euclid(w);
+                            y = y.rem_euclid(h);
+                        }
+
+                        ((0..w).contains(&x) && (0..h).contains(&y)).then_some((y * w + x) as usize)
+                    })
+                    // Wrapping a narrow grid can reach a cell twice or itself
+                    .filter(|j| *j != i)
+                    .unique()
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            width,
+            cells,
+            rules,
+            neighbours,
+            steps: 0,
+            total: 0,
         }
+    }
+
+    // Cascades through a worklist rather than recursion
+    fn step(&mut self) -> Step {
+        let Rules {
+            increment,
+            threshold,
+            cascade,
+            reset,
+            ..
+        } = self.rules;
+        let mut flashed = vec![false; self.cells.len()];
+        let mut queue = Vec::new();
 
-        let mut total = 0;
+        for (i, n) in self.cells.iter_mut().enumerate() {
+            *n = n.saturating_add(increment);
 
-        for (y, row) in self.energy.iter().enumerate() {
-            for x in 0..row.len() {
-                total += run(energy, x, y);
+            if *n >= threshold {
+                flashed[i] = true;
+                queue.push(i);
             }
         }
 
-        for row in energy.iter_mut() {
-            for n in row.iter_mut() {
-                if *n >= 10 {
-                    *n = 0;
+        let mut flashes = Vec::new();
+
+        while let Some(i) = queue.pop() {
+            flashes.push(i);
+
+            let Cascade::Add(energy) = cascade else {
+                continue;
+            };
+
+            for &j in &self.neighbours[i] {
+                if flashed[j] {
+                    continue;
+                }
+
+                self.cells[j] = self.cells[j].saturating_add(energy);
+
+                if self.cells[j] >= threshold {
+                    flashed[j] = true;
+                    queue.push(j);
                 }
             }
         }
 
-        for row in energy.iter_mut() {
-            for n in row.iter_mut() {
-                if *n >= 10 {
-                    *n = 0;
-                }
+        for (n, _) in self.cells.iter_mut().zip(&flashed).filter(|(_, f)| **f) {
+            *n = match reset {
+                Reset::Zero => 0,
+                Reset::Subtract => *n - threshold,
+                Reset::Value(value) => value,
+            };
+        }
+
+        flashes.sort_unstable();
+        self.steps += 1;
+        self.total += flashes.len();
+
+        Step {
+            step: self.steps,
+            flashed: flashes,
+            total: self.total,
+        }
+    }
+
+    // Brent's cycle detection, stepping this automaton as the hare so each
+    // step is checked for synchronisation in order, while holding only a
+    // couple of extra states
+    fn synchronise(&mut self, limit: usize) -> Result<Outcome, Error> {
+        let initial = self.clone();
+        let mut tortoise = self.cells.clone();
+        let mut power = 1;
+        let mut period = 0;
+
+        loop {
+            if self.steps >= limit {
+                bail!("Neither synchronised nor repeated within {} steps", limit);
             }
+
+            let step = self.step();
+
+            if step.flashed.len() == self.cells.len() {
+                return Ok(Outcome::Synchronised(step.step));
+            }
+
+            period += 1;
+
+            if self.cells == tortoise {
+                break;
+            }
+
+            if period == power {
+                tortoise.clone_from(&self.cells);
+                power *= 2;
+                period = 0;
+            }
+        }
+
+        // Replays from the start with the hare `period` steps ahead, meeting
+        // the tortoise where the cycle begins
+        let mut tortoise = initial.clone();
+        let mut hare = initial;
+
+        for _ in 0..period {
+            hare.step();
+        }
+
+        while tortoise.cells != hare.cells {
+            tortoise.step();
+            hare.step();
         }
 
-        total
+        Ok(Outcome::Cycle {
+            start: tortoise.steps,
+            period,
+        })
     }
 
-    fn part_1(&self) -> u64 {
-        let mut total = 0;
-        let mut energy = self.energy.clone();
+    // One map per step, `#` for flashed cells
+    fn write_maps(&self, steps: &[Step], w: &mut impl io::Write) -> io::Result<()> {
+        let width = self.width.max(1);
+
+        for step in steps {
+            let mut map = vec![b'.'; self.cells.len()];
+
+            for i in &step.flashed {
+                map[*i] = b'#';
+            }
+
+            writeln!(w, "Step {}: {} flashes", step.step, step.flashed.len())?;
 
-        for _ in 0..100 {
-            total += self.simulation(&mut energy);
+            for row in map.chunks(width) {
+                w.write_all(row)?;
+                writeln!(w)?;
+            }
+
+            writeln!(w)?;
         }
 
-        total
+        Ok(())
     }
 
-    fn part_2(&self) -> usize {
-        let mut energy = self.energy.clone();
+    fn write_csv(&self, steps: &[Step], w: &mut impl io::Write) -> io::Result<()> {
+        let width = self.width.max(1);
 
-        for i in 1.. {
-            self.simulation(&mut energy);
+        writeln!(w, "step,x,y")?;
 
-            if energy.iter().flat_map(|row| row.iter()).all(|n| *n == 0) {
-                return i;
+        for step in steps {
+            for i in &step.flashed {
+                writeln!(w, "{},{},{}", step.step, i % width, i / width)?;
             }
         }
 
-        unreachable!()
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Step {
+    // Counted from 1
+    step: usize,
+    // Indices of the cells that flashed, in row major order
+    flashed: Vec<usize>,
+    // Flashes over every step so far
+    total: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    // Step on which every cell flashed at once
+    Synchronised(usize),
+    // The state after step `start` recurs every `period` steps
+    Cycle { start: usize, period: usize },
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Synchronised(step) => write!(f, "Synchronised on step {}", step),
+            Self::Cycle { start, period } => write!(
+                f,
+                "Repeats every {} steps from step {} without synchronising",
+                period, start
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Automaton {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in self.cells.chunks(self.width.max(1)) {
+            writeln!(f, "{}", row.iter().join(" "))?;
+        }
+
+        Ok(())
     }
 }
 
 fn main() -> Result<(), Error> {
     let puzzle = Puzzle::from_str(&read_stdin()?)?;
+    let mut args = env::args().skip(1).peekable();
+
+    // `sync [rules]`
+    if args.next_if(|arg| arg == "sync").is_some() {
+        let rules = args.next().map(|s| Rules::from_str(&s)).transpose()?;
+        let mut automaton = puzzle.automaton(rules.unwrap_or_default());
+
+        println!("{}", automaton.synchronise(Automaton::MAX_STEPS)?);
+
+        return Ok(());
+    }
+
+    // `flashes <maps|csv> [steps] [rules]` exports which cells flashed
+    if args.next_if(|arg| arg == "flashes").is_some() {
+        let format = args.next().context("Expected flashes format")?;
+        let steps = args.next().map(|s| usize::from_str(&s)).transpose()?;
+        let rules = args.next().map(|s| Rules::from_str(&s)).transpose()?;
+        let mut automaton = puzzle.automaton(rules.unwrap_or_default());
+        let steps = (0..steps.unwrap_or(100))
+            .map(|_| automaton.step())
+            .collect::<Vec<_>>();
+        let mut stdout = io::BufWriter::new(io::stdout().lock());
+
+        match format.as_str() {
+            "maps" => automaton.write_maps(&steps, &mut stdout)?,
+            "csv" => automaton.write_csv(&steps, &mut stdout)?,
+            _ => bail!("Unknown flashes format: {}", format),
+        }
+
+        return Ok(());
+    }
+
+    // `<rules> [steps]` runs a variant and shows the final grid
+    if let Some(rules) = args.next() {
+        let mut automaton = puzzle.automaton(Rules::from_str(&rules)?);
+        let steps = args.next().map(|s| usize::from_str(&s)).transpose()?;
+
+        for _ in 0..steps.unwrap_or(100) {
+            automaton.step();
+        }
+
+        print!("{}", automaton);
+        println!("Flashes: {}", automaton.total);
+
+        return Ok(());
+    }
 
     println!("Part 1: {}", puzzle.part_1());
-    println!("Part 2: {}", puzzle.part_2());
+    println!("Part 2: {}", puzzle.part_2()?);
 
     Ok(())
 }
@@ -143,7 +500,122 @@ mod test {
 
     #[test]
     fn part_2() -> Result<(), Error> {
-        assert_eq!(Puzzle::from_str(INPUT)?.part_2(), 195);
+        assert_eq!(Puzzle::from_str(INPUT)?.part_2()?, 195);
+        Ok(())
+    }
+
+    #[test]
+    fn rules() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str("989")?;
+        let mut octopus = puzzle.automaton(Rules::default());
+
+        assert_eq!(octopus.step().flashed, [0, 1, 2]);
+        assert_eq!(octopus.cells, [0, 0, 0]);
+
+        let mut automaton = puzzle.automaton(Rules::from_str("cascade=off,reset=subtract")?);
+
+        assert_eq!(automaton.step().flashed, [0, 2]);
+        assert_eq!(automaton.cells, [0, 9, 0]);
+
+        // Both ends feed the middle before it flashes
+        let mut automaton = puzzle.automaton(Rules::from_str("threshold=11,increment=2")?);
+
+        assert_eq!(automaton.step().flashed.len(), 3);
+
+        // Only the orthogonal neighbours of the flashing corner gain energy
+        let puzzle = Puzzle::from_str("90\n08")?;
+        let mut automaton = puzzle.automaton(Rules::from_str("neighbourhood=4")?);
+
+        assert_eq!(automaton.step().flashed, [0]);
+        assert_eq!(automaton.cells, [0, 2, 2, 9]);
+
+        let automaton = puzzle.automaton(Rules::from_str("wrap=true")?);
+
+        assert_eq!(automaton.neighbours[0], [3, 2, 1]);
+        assert!(Rules::from_str("colour=red").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn synchronise() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str(INPUT)?;
+        let mut automaton = puzzle.automaton(Rules::default());
+        let step = automaton.step();
+
+        assert!(step.flashed.is_empty());
+
+        let step = automaton.step();
+
+        assert_eq!(step.flashed.len(), 35);
+        assert_eq!(step.total, 35);
+        assert_eq!(automaton.synchronise(1000)?, Outcome::Synchronised(195));
+
+        // Neighbours never catch up without cascading
+        let puzzle = Puzzle::from_str("12")?;
+        let mut automaton = puzzle.automaton(Rules::from_str("cascade=off")?);
+
+        assert_eq!(
+            automaton.synchronise(1000)?,
+            Outcome::Cycle {
+                start: 0,
+                period: 10
+            }
+        );
+
+        let mut automaton = puzzle.automaton(Rules::from_str("cascade=off,reset=subtract")?);
+
+        assert!(automaton.synchronise(1000).is_ok());
+
+        // A cycle that starts late
+        let puzzle = Puzzle::from_str(INPUT)?;
+        let mut automaton = puzzle.automaton(Rules::from_str("neighbourhood=4")?);
+
+        assert_eq!(
+            automaton.synchronise(1000)?,
+            Outcome::Cycle {
+                start: 108,
+                period: 8
+            }
+        );
+
+        // A large grid that never repeats gives up at the limit
+        let puzzle = Puzzle {
+            energy: vec![vec![1; 60]; 60],
+        };
+        let mut automaton = puzzle.automaton(Rules::from_str("threshold=100000000")?);
+
+        assert!(automaton.synchronise(5000).is_err());
+        assert_eq!(automaton.steps, 5000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn flash_maps() -> Result<(), Error> {
+        let puzzle = Puzzle::from_str("11111\n19991\n19191\n19991\n11111")?;
+        let mut automaton = puzzle.automaton(Rules::default());
+        let steps = (0..2).map(|_| automaton.step()).collect::<Vec<_>>();
+        let mut maps = Vec::new();
+
+        automaton.write_maps(&steps, &mut maps)?;
+
+        assert_eq!(
+            String::from_utf8(maps)?,
+            "Step 1: 9 flashes\n.....\n.###.\n.###.\n.###.\n.....\n\n\
+             Step 2: 0 flashes\n.....\n.....\n.....\n.....\n.....\n\n"
+        );
+
+        let mut csv = Vec::new();
+
+        automaton.write_csv(&steps, &mut csv)?;
+
+        let csv = String::from_utf8(csv)?;
+
+        assert_eq!(
+            csv.lines().take(2).collect::<Vec<_>>(),
+            ["step,x,y", "1,1,1"]
+        );
+        assert_eq!(csv.lines().count(), 10);
         Ok(())
     }
 }